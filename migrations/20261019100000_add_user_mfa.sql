@@ -0,0 +1,15 @@
+-- Add migration script here
+ALTER TABLE users
+    ADD COLUMN totp_secret TEXT,                             -- base32 TOTP secret, set on enrollment
+    ADD COLUMN totp_enabled BOOLEAN NOT NULL DEFAULT FALSE,  -- flipped once the first code is confirmed
+    ADD COLUMN totp_last_step BIGINT;                        -- last accepted time step, blocks code replay
+
+CREATE TABLE recovery_codes (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    code_hash TEXT NOT NULL,        -- argon2 hash of the one-time code, never plaintext!
+    used_at TIMESTAMPTZ,            -- set once the code has been redeemed
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX recovery_codes_user_id_idx ON recovery_codes (user_id);
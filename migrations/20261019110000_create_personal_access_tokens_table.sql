@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE personal_access_tokens (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,                 -- label chosen by the user, e.g. "ci"
+    token_prefix TEXT NOT NULL,         -- first characters of the token, for display only
+    token_hash TEXT UNIQUE NOT NULL,    -- sha256 of the full token, never plaintext!
+    scopes TEXT[] NOT NULL,             -- e.g. {"tasks:read","tasks:write"}
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ,
+    expires_at TIMESTAMPTZ,             -- NULL means it never expires
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX personal_access_tokens_user_id_idx ON personal_access_tokens (user_id);
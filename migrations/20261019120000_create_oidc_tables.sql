@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TABLE oidc_login_states (
+    state TEXT PRIMARY KEY,         -- random value echoed back by the provider
+    nonce TEXT NOT NULL,            -- must come back inside the ID token
+    code_verifier TEXT NOT NULL,    -- PKCE verifier, sent on code exchange
+    link_user_id BIGINT REFERENCES users(id) ON DELETE CASCADE, -- set when linking to a logged-in user
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE user_identities (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    issuer TEXT NOT NULL,           -- `iss` of the identity provider
+    subject TEXT NOT NULL,          -- `sub` of the user at that provider
+    email TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_login_at TIMESTAMPTZ,
+    UNIQUE (issuer, subject)
+);
+
+CREATE INDEX user_identities_user_id_idx ON user_identities (user_id);
@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE audit_events (
+    id BIGSERIAL PRIMARY KEY,
+    event_type TEXT NOT NULL,       -- e.g. "login", "sign_up", "token_rejected"
+    outcome TEXT NOT NULL,          -- "success" or "failure"
+    user_id BIGINT REFERENCES users(id) ON DELETE SET NULL,
+    username TEXT,                  -- as submitted, also kept for unknown users
+    ip_address INET,
+    user_agent TEXT,
+    reason TEXT,                    -- why it failed, NULL on success
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX audit_events_created_at_idx ON audit_events (created_at);
+CREATE INDEX audit_events_user_id_idx ON audit_events (user_id);
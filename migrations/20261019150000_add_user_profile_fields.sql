@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE users
+    ADD COLUMN display_name TEXT,
+    ADD COLUMN time_zone TEXT NOT NULL DEFAULT 'UTC',   -- IANA name, e.g. "Europe/Berlin"
+    ADD COLUMN locale TEXT NOT NULL DEFAULT 'en';       -- BCP 47 tag, e.g. "en-GB"
@@ -0,0 +1,6 @@
+-- Add migration script here
+ALTER TABLE users
+    ADD COLUMN deletion_scheduled_at TIMESTAMPTZ; -- hard purge happens once this has passed, NULL if not leaving
+
+CREATE INDEX users_deletion_scheduled_at_idx ON users (deletion_scheduled_at)
+    WHERE deletion_scheduled_at IS NOT NULL;
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE users
+    ADD COLUMN role TEXT NOT NULL DEFAULT 'user' CHECK (role IN ('user', 'admin')),
+    ADD COLUMN disabled_at TIMESTAMPTZ; -- set by an admin, blocks sign in and every token
@@ -0,0 +1,11 @@
+-- Add migration script here
+-- token buckets of the postgres rate limit backend, shared by every replica.
+-- Unlogged: losing them in a crash only means everyone starts with a full bucket
+CREATE UNLOGGED TABLE rate_limit_buckets (
+    key TEXT PRIMARY KEY,               -- route group and client, e.g. "auth:ip:203.0.113.7"
+    tokens DOUBLE PRECISION NOT NULL,   -- left after the last request
+    allowed BOOLEAN NOT NULL,           -- whether the last request got a token
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX rate_limit_buckets_updated_at_idx ON rate_limit_buckets (updated_at);
@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE mfa_challenges (
+    id UUID PRIMARY KEY,            -- carried as `jti` in the MFA pending token
+    user_id BIGINT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    attempts INTEGER NOT NULL DEFAULT 0, -- codes tried, the challenge is dead once it hits the cap
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    consumed_at TIMESTAMPTZ         -- set by the successful attempt, a token works once
+);
+
+CREATE INDEX mfa_challenges_user_id_idx ON mfa_challenges (user_id);
+
+ALTER TABLE users
+    ADD COLUMN mfa_failed_attempts INTEGER NOT NULL DEFAULT 0, -- across every challenge and re-authentication
+    ADD COLUMN mfa_locked_until TIMESTAMPTZ; -- second factors are refused until then
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE users
+    ADD COLUMN has_password BOOLEAN NOT NULL DEFAULT TRUE; -- false for users provisioned through OIDC, their password_hash is random
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE mfa_challenges
+    ADD COLUMN new_password_hash TEXT, -- upgraded hash from the first factor, stored once the second factor passes
+    ADD COLUMN old_password_hash TEXT; -- the hash it replaces, a password change in between wins
@@ -1,12 +1,67 @@
-use axum::Json;
+use axum::{
+    Json,
+    http::{HeaderMap, header::ACCEPT},
+};
 use serde::Serialize;
+use utoipa::{
+    ToSchema,
+    openapi::{Object, ObjectBuilder, Type},
+};
 
-use super::errors::AppError;
+use super::{errors::AppError, telemetry};
 
-#[derive(Debug, Serialize)]
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+tokio::task_local! {
+    /// Whether the client asked for RFC 7807 errors, set by `middleware_trace`.
+    pub static WANTS_PROBLEM_JSON: bool;
+}
+
+/// The body of every successful response.
+#[derive(Debug, Serialize, ToSchema)]
 pub struct APIResponse<T> {
     pub response_message: String,
     pub response_data: T,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// The body of every error response. Same shape as `APIResponse` with a null
+/// `response_data`, so clients can read both the same way.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub response_message: String,
+    #[schema(schema_with = null_schema)]
+    pub response_data: (),
+    pub error: APIError,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct APIError {
+    /// Stable, snake_case identifier clients can match on.
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+/// One invalid input, `field` is a dotted path such as `due_date` or
+/// `items[0].name`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
 }
 
 impl<T: Serialize> APIResponse<T> {
@@ -14,8 +69,60 @@ impl<T: Serialize> APIResponse<T> {
         Json(Self {
             response_message: "success".to_string(),
             response_data: data,
+            request_id: telemetry::current_request_id(),
         })
     }
 }
 
+impl ErrorResponse {
+    pub fn new(error: APIError) -> Self {
+        Self {
+            response_message: error.message.clone(),
+            response_data: (),
+            error,
+            request_id: telemetry::current_request_id(),
+        }
+    }
+}
+
+/// RFC 7807 body, sent instead of the envelope when the client accepts
+/// `application/problem+json`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    pub code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
+}
+
+/// True when `Accept` lists `application/problem+json`.
+pub fn accepts_problem_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|media| {
+            media
+                .split(';')
+                .next()
+                .is_some_and(|media| media.trim().eq_ignore_ascii_case(PROBLEM_JSON))
+        })
+}
+
+/// Whether the request being handled asked for problem details.
+pub fn wants_problem_json() -> bool {
+    WANTS_PROBLEM_JSON.try_with(|wants| *wants).unwrap_or(false)
+}
+
 pub type AppResponse<T> = Result<Json<APIResponse<T>>, AppError>;
+
+fn null_schema() -> Object {
+    ObjectBuilder::new().schema_type(Type::Null).build()
+}
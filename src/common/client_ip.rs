@@ -0,0 +1,174 @@
+//! The address a request came from. Behind a load balancer the peer is the
+//! proxy, the client is then read from `Forwarded` or `X-Forwarded-For`,
+//! trusting only the hops appended by proxies in `server.trusted_proxies`.
+
+use axum::http::{HeaderMap, HeaderName};
+use sqlx::types::ipnetwork::IpNetwork;
+use std::net::{IpAddr, SocketAddr};
+
+const FORWARDED: HeaderName = HeaderName::from_static("forwarded");
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+/// The resolved client address, put in the request extensions by
+/// `middleware_client_ip`.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+/// Walks the forwarded chain from the peer backwards. Every hop a trusted
+/// proxy vouches for is skipped, the first one it doesn't is the client.
+/// Anything before that was written by the client and can't be believed.
+pub fn resolve(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNetwork]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted_proxies.iter().any(|proxy| proxy.contains(ip));
+    if !is_trusted(peer) {
+        return peer;
+    }
+
+    // Forwarded is the standard, X-Forwarded-For only counts without it
+    let chain = match headers.contains_key(FORWARDED) {
+        true => hops(headers, FORWARDED, forwarded_for),
+        false => hops(headers, X_FORWARDED_FOR, |hop| Some(hop)),
+    };
+
+    let mut client = peer;
+    for hop in chain.iter().rev() {
+        // an unknown or garbled hop ends the chain, the last good one stands
+        let Some(ip) = hop.as_deref().and_then(parse_ip) else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+
+    client
+}
+
+// every hop across all instances of the header, in order
+fn hops(
+    headers: &HeaderMap,
+    name: HeaderName,
+    node: impl Fn(&str) -> Option<&str>,
+) -> Vec<Option<String>> {
+    headers
+        .get_all(name)
+        .iter()
+        .flat_map(|value| match value.to_str() {
+            Ok(value) => value
+                .split(',')
+                .map(|hop| node(hop.trim()).map(str::to_string))
+                .collect(),
+            Err(_) => vec![None],
+        })
+        .collect()
+}
+
+// the `for` parameter of one Forwarded element
+fn forwarded_for(element: &str) -> Option<&str> {
+    element.split(';').find_map(|pair| {
+        let (key, value) = pair.trim().split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("for")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+// a bare address, an IPv4 address with a port, or a bracketed IPv6 address
+// with or without one
+fn parse_ip(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    node.strip_prefix('[')?.split_once(']')?.0.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROXY: &str = "10.0.0.5";
+
+    fn resolve_with(peer: &str, headers: &[(&str, &str)]) -> String {
+        let trusted: Vec<IpNetwork> = vec!["10.0.0.0/8".parse().unwrap()];
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(
+                HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+
+        resolve(peer.parse().unwrap(), &map, &trusted).to_string()
+    }
+
+    #[test]
+    fn untrusted_peers_are_the_client() {
+        assert_eq!(
+            resolve_with("192.0.2.1", &[("x-forwarded-for", "198.51.100.7")]),
+            "192.0.2.1"
+        );
+    }
+
+    #[test]
+    fn only_hops_added_by_trusted_proxies_are_believed() {
+        // the client made up the first entry, our proxies added the rest
+        assert_eq!(
+            resolve_with(
+                PROXY,
+                &[("x-forwarded-for", "203.0.113.9, 198.51.100.7, 10.0.0.2")]
+            ),
+            "198.51.100.7"
+        );
+        assert_eq!(
+            resolve_with(
+                PROXY,
+                &[
+                    ("x-forwarded-for", "203.0.113.9"),
+                    ("x-forwarded-for", "198.51.100.7")
+                ]
+            ),
+            "198.51.100.7"
+        );
+    }
+
+    #[test]
+    fn forwarded_wins_over_x_forwarded_for() {
+        assert_eq!(
+            resolve_with(
+                PROXY,
+                &[
+                    ("x-forwarded-for", "203.0.113.9"),
+                    (
+                        "forwarded",
+                        r#"for=198.51.100.7;proto=https, for="[2001:db8::17]:4711""#
+                    )
+                ]
+            ),
+            "2001:db8::17"
+        );
+        assert_eq!(
+            resolve_with(PROXY, &[("forwarded", "for=198.51.100.7:8080;by=10.0.0.5")]),
+            "198.51.100.7"
+        );
+    }
+
+    #[test]
+    fn unknown_hops_end_the_chain() {
+        assert_eq!(
+            resolve_with(PROXY, &[("forwarded", "for=198.51.100.7, for=unknown")]),
+            PROXY
+        );
+        assert_eq!(
+            resolve_with(
+                PROXY,
+                &[("x-forwarded-for", "198.51.100.7, 10.0.0.2, junk")]
+            ),
+            PROXY
+        );
+        assert_eq!(resolve_with(PROXY, &[]), PROXY);
+    }
+}
@@ -0,0 +1,144 @@
+use axum::http::HeaderValue;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::config::{CorsConfig, OriginPattern};
+
+/// The CORS layer for `config`. Origins that match no entry get no CORS
+/// headers at all, so the browser blocks the response.
+pub fn layer(config: &CorsConfig) -> CorsLayer {
+    let allow_origin = match config.allowed_origins.contains(&OriginPattern::Any) {
+        true => AllowOrigin::any(),
+        false => {
+            let patterns = config.allowed_origins.clone();
+            AllowOrigin::predicate(move |origin: &HeaderValue, _| {
+                origin
+                    .to_str()
+                    .is_ok_and(|origin| patterns.iter().any(|pattern| pattern.matches(origin)))
+            })
+        }
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(config.allowed_methods.clone())
+        .allow_headers(config.allowed_headers.clone())
+        .expose_headers(config.exposed_headers.clone())
+        .allow_credentials(config.allow_credentials)
+        .max_age(config.max_age)
+}
+
+// A * in the host matches one or more letters, digits, dots or dashes
+// (subdomains), in the port one or more digits. Scheme, host and port are
+// matched separately so a * never reaches into another part
+impl OriginPattern {
+    pub fn matches(&self, origin: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Exact(exact) => exact.eq_ignore_ascii_case(origin),
+            Self::Wildcard(pattern) => {
+                let (Some((scheme, authority)), Some((origin_scheme, origin_authority))) =
+                    (pattern.split_once("://"), origin.split_once("://"))
+                else {
+                    return false;
+                };
+                let (host, port) = split_port(authority);
+                let (origin_host, origin_port) = split_port(origin_authority);
+                let is_host = |c: u8| c.is_ascii_alphanumeric() || c == b'.' || c == b'-';
+
+                scheme.eq_ignore_ascii_case(origin_scheme)
+                    && wildcard_match(host.as_bytes(), origin_host.as_bytes(), is_host)
+                    && match (port, origin_port) {
+                        (None, None) => true,
+                        (Some(port), Some(origin_port)) => {
+                            wildcard_match(port.as_bytes(), origin_port.as_bytes(), |c: u8| {
+                                c.is_ascii_digit()
+                            })
+                        }
+                        _ => false,
+                    }
+            }
+        }
+    }
+
+    /// Whether the host itself is a pattern, e.g. `https://*.example.com`.
+    /// Port only patterns like `http://localhost:*` are not.
+    pub fn has_wildcard_host(&self) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Exact(_) => false,
+            Self::Wildcard(pattern) => pattern
+                .split_once("://")
+                .is_some_and(|(_, authority)| split_port(authority).0.contains('*')),
+        }
+    }
+}
+
+// "host:port" into its parts, an IPv6 host keeps its colons
+fn split_port(authority: &str) -> (&str, Option<&str>) {
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, Some(port)),
+        _ => (authority, None),
+    }
+}
+
+fn wildcard_match(pattern: &[u8], value: &[u8], allowed: impl Fn(u8) -> bool + Copy) -> bool {
+    match pattern.split_first() {
+        None => value.is_empty(),
+        Some((b'*', rest)) => (1..=value.len())
+            .take_while(|&len| allowed(value[len - 1]))
+            .any(|len| wildcard_match(rest, &value[len..], allowed)),
+        Some((c, rest)) => {
+            value.first().is_some_and(|v| v.eq_ignore_ascii_case(c))
+                && wildcard_match(rest, &value[1..], allowed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(value: &str) -> OriginPattern {
+        value.parse().expect("valid pattern")
+    }
+
+    #[test]
+    fn subdomain_wildcard_stays_inside_the_host() {
+        let pattern = pattern("https://*.example.com");
+
+        assert!(pattern.matches("https://app.example.com"));
+        assert!(pattern.matches("https://a.b.example.com"));
+        assert!(pattern.matches("https://APP.Example.com"));
+        for origin in [
+            "https://example.com",
+            "https://evil.com/.example.com",
+            "https://a.example.com.evil.com",
+            "https://evil-example.com",
+            "https://a.example.com:8443",
+            "http://a.example.com",
+            "https://user@a.example.com",
+        ] {
+            assert!(!pattern.matches(origin), "{origin}");
+        }
+    }
+
+    #[test]
+    fn port_wildcard_only_matches_digits() {
+        let pattern = pattern("http://localhost:*");
+
+        assert!(pattern.matches("http://localhost:3000"));
+        for origin in [
+            "http://localhost",
+            "https://localhost:3000",
+            "http://localhost:3000.evil.com",
+            "http://localhost.evil.com:3000",
+            "http://localhost:",
+        ] {
+            assert!(!pattern.matches(origin), "{origin}");
+        }
+
+        let ipv6 = self::pattern("http://[::1]:*");
+        assert!(ipv6.matches("http://[::1]:8080"));
+        assert!(!ipv6.matches("http://[::1]"));
+    }
+}
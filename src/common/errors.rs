@@ -1,7 +1,20 @@
 use thiserror::Error;
 
-use axum::{Json, http::StatusCode, response::IntoResponse};
-use serde::{Deserialize, Serialize};
+use super::{
+    api::{self, APIError, ErrorResponse, FieldError, ProblemDetails},
+    telemetry,
+};
+
+use axum::{
+    Json,
+    http::{
+        HeaderValue, StatusCode,
+        header::{CONTENT_TYPE, RETRY_AFTER, WWW_AUTHENTICATE},
+    },
+    response::IntoResponse,
+};
+
+const REALM: &str = "tasks_backend";
 
 #[derive(Debug, Error)]
 pub enum AppError {
@@ -11,86 +24,608 @@ pub enum AppError {
     #[error("User already exists")]
     UserAlreadyExists,
 
+    #[error("Account deletion already scheduled")]
+    AccountDeletionPending,
+
+    #[error("Account disabled")]
+    AccountDisabled,
+
+    #[error("Recent sign in required")]
+    ReauthenticationRequired,
+
     #[error("Password hasing failed")]
     PasswordHashingFailed,
 
-    #[error("Database query failed")]
-    DatabaseQueryFailed,
-
     #[error("Invalid login credentials")]
     InvalidUserCredentials,
 
     #[error("JWT creation failed")]
     JWTCreationFailed,
 
-    #[error("Error fetching tasks")]
-    ErrorFetchingTasks,
-
     #[error("Unauthorized")]
     Unauthorized,
 
     #[error("Invalid Token")]
     InvalidToken,
 
-    #[error("Task creation failed")]
-    TaskCreationFailed,
+    #[error("Missing token")]
+    MissingToken,
 
-    #[error("Task not found")]
-    TaskNotFound,
+    #[error("Token expired")]
+    ExpiredToken,
+
+    #[error("Token not yet valid")]
+    TokenNotYetValid,
 
-    #[error("Error updating task")]
-    ErrorUpdatingTask,
+    #[error("Token issued by an unexpected issuer")]
+    InvalidTokenIssuer,
 
-    #[error("Error deleting task")]
-    ErrorDeletingTask,
+    #[error("Token issued for a different audience")]
+    InvalidTokenAudience,
+
+    #[error("Session revoked or expired")]
+    SessionRevoked,
+
+    #[error("Task not found")]
+    TaskNotFound,
 
     #[error("Not found")]
     NotFound,
+
+    #[error("MFA enrollment failed")]
+    MfaEnrollmentFailed,
+
+    #[error("Two-factor authentication already enabled")]
+    MfaAlreadyEnabled,
+
+    #[error("No pending two-factor enrollment")]
+    MfaNotPending,
+
+    #[error("Invalid two-factor code")]
+    InvalidMfaCode,
+
+    #[error("Too many invalid two-factor codes, retry in {retry_after}s")]
+    MfaLocked { retry_after: u64 },
+
+    #[error("Invalid access token request")]
+    InvalidAccessTokenRequest,
+
+    #[error("OIDC sign in is not configured")]
+    OidcNotConfigured,
+
+    #[error("OIDC provider error")]
+    OidcProviderError,
+
+    #[error("OIDC sign in failed")]
+    OidcLoginFailed,
+
+    #[error("OIDC sign in state invalid or expired")]
+    OidcStateInvalid,
+
+    #[error("OIDC identity already linked to another user")]
+    OidcIdentityAlreadyLinked,
+
+    #[error("OIDC identity not linked to a user")]
+    OidcAccountNotLinked,
+
+    #[error("Invalid audit log query")]
+    InvalidAuditQuery,
+
+    #[error("Validation failed")]
+    Validation(Vec<FieldError>),
+
+    #[error("Malformed request body: {0}")]
+    MalformedBody(String),
+
+    #[error("Unsupported media type")]
+    UnsupportedMediaType,
+
+    #[error("Method not allowed")]
+    MethodNotAllowed,
+
+    #[error("Rate limit exceeded, retry in {retry_after}s")]
+    RateLimited { retry_after: u64 },
+
+    #[error("Request body too large")]
+    PayloadTooLarge,
+
+    #[error("Request timed out")]
+    RequestTimeout,
+
+    #[error("Too many requests in flight")]
+    Overloaded,
+
+    #[error("Database error {context}")]
+    Database {
+        context: &'static str,
+        #[source]
+        source: sqlx::Error,
+    },
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> axum::response::Response {
-        let (status, error_message) = match self {
+/// How a database failure should be reported to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatabaseFailure {
+    /// A unique constraint rejected the write.
+    UniqueViolation,
+    /// The write references a row that doesn't exist.
+    ForeignKeyViolation,
+    /// Lost a serialization or deadlock race, safe to retry.
+    SerializationFailure,
+    /// No connection could be had in time, or the database is unreachable.
+    Unavailable,
+    Other,
+}
+
+impl DatabaseFailure {
+    pub fn classify(error: &sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) => {
+                Self::Unavailable
+            }
+            sqlx::Error::Database(db) => match db.kind() {
+                sqlx::error::ErrorKind::UniqueViolation => Self::UniqueViolation,
+                sqlx::error::ErrorKind::ForeignKeyViolation => Self::ForeignKeyViolation,
+                // serialization_failure and deadlock_detected
+                _ if matches!(db.code().as_deref(), Some("40001" | "40P01")) => {
+                    Self::SerializationFailure
+                }
+                _ => Self::Other,
+            },
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Attached to every error response so middleware can tell which variant
+/// produced it, e.g. for the `errors_total` metric.
+#[derive(Debug, Clone)]
+pub struct ErrorVariant(pub &'static str);
+
+impl AppError {
+    /// Wraps a sqlx error, `context` says what was being done, e.g.
+    /// `.map_err(AppError::database("loading profile"))`.
+    pub fn database(context: &'static str) -> impl FnOnce(sqlx::Error) -> Self {
+        move |source| Self::Database { context, source }
+    }
+
+    /// A `Validation` error for a single field, e.g.
+    /// `AppError::invalid_field("locale", "is not a BCP 47 language tag")`.
+    pub fn invalid_field(field: &str, message: &str) -> Self {
+        Self::Validation(vec![FieldError::new(field, message)])
+    }
+
+    /// The error and every source below it, e.g. `Database error loading
+    /// profile: pool timed out while waiting for an open connection`.
+    pub fn chain(&self) -> String {
+        let mut chain = self.to_string();
+        let mut source = std::error::Error::source(self);
+
+        while let Some(error) = source {
+            chain.push_str(": ");
+            chain.push_str(&error.to_string());
+            source = error.source();
+        }
+
+        chain
+    }
+
+    /// The variant name, e.g. `TaskNotFound`.
+    pub fn variant(&self) -> &'static str {
+        match self {
+            Self::SignupFailed => "SignupFailed",
+            Self::UserAlreadyExists => "UserAlreadyExists",
+            Self::AccountDeletionPending => "AccountDeletionPending",
+            Self::AccountDisabled => "AccountDisabled",
+            Self::ReauthenticationRequired => "ReauthenticationRequired",
+            Self::PasswordHashingFailed => "PasswordHashingFailed",
+            Self::InvalidUserCredentials => "InvalidUserCredentials",
+            Self::JWTCreationFailed => "JWTCreationFailed",
+            Self::Unauthorized => "Unauthorized",
+            Self::InvalidToken => "InvalidToken",
+            Self::MissingToken => "MissingToken",
+            Self::ExpiredToken => "ExpiredToken",
+            Self::TokenNotYetValid => "TokenNotYetValid",
+            Self::InvalidTokenIssuer => "InvalidTokenIssuer",
+            Self::InvalidTokenAudience => "InvalidTokenAudience",
+            Self::SessionRevoked => "SessionRevoked",
+            Self::TaskNotFound => "TaskNotFound",
+            Self::NotFound => "NotFound",
+            Self::MfaEnrollmentFailed => "MfaEnrollmentFailed",
+            Self::MfaAlreadyEnabled => "MfaAlreadyEnabled",
+            Self::MfaNotPending => "MfaNotPending",
+            Self::InvalidMfaCode => "InvalidMfaCode",
+            Self::MfaLocked { .. } => "MfaLocked",
+            Self::InvalidAccessTokenRequest => "InvalidAccessTokenRequest",
+            Self::OidcNotConfigured => "OidcNotConfigured",
+            Self::OidcProviderError => "OidcProviderError",
+            Self::OidcLoginFailed => "OidcLoginFailed",
+            Self::OidcStateInvalid => "OidcStateInvalid",
+            Self::OidcIdentityAlreadyLinked => "OidcIdentityAlreadyLinked",
+            Self::OidcAccountNotLinked => "OidcAccountNotLinked",
+            Self::InvalidAuditQuery => "InvalidAuditQuery",
+            Self::Validation(_) => "Validation",
+            Self::MalformedBody(_) => "MalformedBody",
+            Self::UnsupportedMediaType => "UnsupportedMediaType",
+            Self::MethodNotAllowed => "MethodNotAllowed",
+            Self::RateLimited { .. } => "RateLimited",
+            Self::PayloadTooLarge => "PayloadTooLarge",
+            Self::RequestTimeout => "RequestTimeout",
+            Self::Overloaded => "Overloaded",
+            Self::Database { .. } => "Database",
+        }
+    }
+
+    /// Seconds a client should wait before retrying, for transient failures.
+    fn retry_after(&self) -> Option<u64> {
+        match self {
+            Self::Database { source, .. } => match DatabaseFailure::classify(source) {
+                DatabaseFailure::SerializationFailure | DatabaseFailure::Unavailable => Some(1),
+                _ => None,
+            },
+            Self::RateLimited { retry_after } | Self::MfaLocked { retry_after } => {
+                Some(*retry_after)
+            }
+            Self::Overloaded => Some(1),
+            _ => None,
+        }
+    }
+
+    /// RFC 6750 challenge for bearer token failures, `None` for everything else.
+    fn www_authenticate(&self) -> Option<String> {
+        let description = match self {
+            // no error code when no credentials were sent at all
+            Self::MissingToken => return Some(format!(r#"Bearer realm="{}""#, REALM)),
+            Self::InvalidToken => "The access token is malformed or its signature is invalid",
+            Self::ExpiredToken => "The access token expired",
+            Self::TokenNotYetValid => "The access token is not valid yet",
+            Self::InvalidTokenIssuer => "The access token was issued by an unexpected issuer",
+            Self::InvalidTokenAudience => "The access token was issued for another audience",
+            Self::SessionRevoked => "The session was signed out",
+            _ => return None,
+        };
+
+        Some(format!(
+            r#"Bearer realm="{}", error="invalid_token", error_description="{}""#,
+            REALM, description
+        ))
+    }
+}
+
+impl AppError {
+    /// Status, stable error code and client-facing message. Codes are part
+    /// of the API contract: clients match on them, so never rename one.
+    fn describe(&self) -> (StatusCode, &'static str, &'static str) {
+        match self {
             // --- User related ---
             Self::SignupFailed => (
                 StatusCode::INTERNAL_SERVER_ERROR,
+                "signup_failed",
                 "Sign-up process failed unexpectedly",
             ),
-            Self::UserAlreadyExists => (StatusCode::CONFLICT, "The user already exists"),
+            Self::UserAlreadyExists => (
+                StatusCode::CONFLICT,
+                "user_already_exists",
+                "The user already exists",
+            ),
+            Self::AccountDisabled => (
+                StatusCode::FORBIDDEN,
+                "account_disabled",
+                "This account has been disabled",
+            ),
+            Self::ReauthenticationRequired => (
+                StatusCode::FORBIDDEN,
+                "reauthentication_required",
+                "Sign in again to confirm this action",
+            ),
+            Self::AccountDeletionPending => (
+                StatusCode::CONFLICT,
+                "account_deletion_pending",
+                "Account deletion is already scheduled",
+            ),
             Self::PasswordHashingFailed => (
                 StatusCode::INTERNAL_SERVER_ERROR,
+                "password_hashing_failed",
                 "Passoword hashing failed",
             ),
-            Self::InvalidUserCredentials => (StatusCode::UNAUTHORIZED, "Invalid email or password"),
+            Self::InvalidUserCredentials => (
+                StatusCode::UNAUTHORIZED,
+                "invalid_credentials",
+                "Invalid email or password",
+            ),
             Self::JWTCreationFailed => (
                 StatusCode::INTERNAL_SERVER_ERROR,
+                "jwt_creation_failed",
                 "Failed to create authentication token(JWT)",
             ),
-            Self::Unauthorized => (StatusCode::FORBIDDEN, "Unauthorized to access"),
-            Self::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid token"),
+            Self::Unauthorized => (StatusCode::FORBIDDEN, "forbidden", "Unauthorized to access"),
+            Self::InvalidToken => (StatusCode::UNAUTHORIZED, "invalid_token", "Invalid token"),
+            Self::MissingToken => (
+                StatusCode::UNAUTHORIZED,
+                "missing_token",
+                "Missing bearer token",
+            ),
+            Self::ExpiredToken => (
+                StatusCode::UNAUTHORIZED,
+                "expired_token",
+                "Token has expired",
+            ),
+            Self::TokenNotYetValid => (
+                StatusCode::UNAUTHORIZED,
+                "token_not_yet_valid",
+                "Token is not valid yet",
+            ),
+            Self::InvalidTokenIssuer => (
+                StatusCode::UNAUTHORIZED,
+                "invalid_token_issuer",
+                "Token has the wrong issuer",
+            ),
+            Self::InvalidTokenAudience => (
+                StatusCode::UNAUTHORIZED,
+                "invalid_token_audience",
+                "Token has the wrong audience",
+            ),
+            Self::SessionRevoked => (
+                StatusCode::UNAUTHORIZED,
+                "session_revoked",
+                "Session has been signed out",
+            ),
 
-            // --- Task-related ---
-            Self::ErrorFetchingTasks => (
+            // --- MFA related ---
+            Self::MfaEnrollmentFailed => (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "Error getching all user tasks",
+                "mfa_enrollment_failed",
+                "Failed to set up two-factor authentication",
+            ),
+            Self::MfaAlreadyEnabled => (
+                StatusCode::CONFLICT,
+                "mfa_already_enabled",
+                "Two-factor authentication is already enabled",
+            ),
+            Self::MfaNotPending => (
+                StatusCode::BAD_REQUEST,
+                "mfa_not_pending",
+                "Start two-factor enrollment before confirming it",
+            ),
+            Self::InvalidMfaCode => (
+                StatusCode::UNAUTHORIZED,
+                "invalid_mfa_code",
+                "Invalid two-factor code",
+            ),
+            Self::MfaLocked { .. } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "mfa_locked",
+                "Too many invalid two-factor codes, try again later",
+            ),
+
+            // --- Access token related ---
+            Self::InvalidAccessTokenRequest => (
+                StatusCode::BAD_REQUEST,
+                "invalid_access_token_request",
+                "Access tokens need a name, at least one scope and a positive expiry",
+            ),
+
+            // --- OIDC related ---
+            Self::OidcNotConfigured => (
+                StatusCode::NOT_FOUND,
+                "oidc_not_configured",
+                "Single sign-on is not enabled",
+            ),
+            Self::OidcProviderError => (
+                StatusCode::BAD_GATEWAY,
+                "oidc_provider_error",
+                "The identity provider could not be reached",
+            ),
+            Self::OidcLoginFailed => (
+                StatusCode::UNAUTHORIZED,
+                "oidc_login_failed",
+                "Single sign-on failed",
+            ),
+            Self::OidcStateInvalid => (
+                StatusCode::BAD_REQUEST,
+                "oidc_state_invalid",
+                "Sign-in attempt expired or was already used, please start again",
+            ),
+            Self::OidcIdentityAlreadyLinked => (
+                StatusCode::CONFLICT,
+                "oidc_identity_already_linked",
+                "This identity is already linked to another account",
+            ),
+            Self::OidcAccountNotLinked => (
+                StatusCode::FORBIDDEN,
+                "oidc_account_not_linked",
+                "No account is linked to this identity",
+            ),
+
+            // --- Admin related ---
+            Self::InvalidAuditQuery => (
+                StatusCode::BAD_REQUEST,
+                "invalid_audit_query",
+                "Invalid audit log filter",
+            ),
+
+            // --- Task-related ---
+            Self::TaskNotFound => (StatusCode::NOT_FOUND, "task_not_found", "Task not found"),
+
+            // --- Request related ---
+            Self::Validation(_) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "validation_failed",
+                "The request has invalid fields",
+            ),
+            Self::MalformedBody(_) => (
+                StatusCode::BAD_REQUEST,
+                "malformed_body",
+                "The request body is not valid JSON",
+            ),
+            Self::UnsupportedMediaType => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+                "Expected a request with Content-Type: application/json",
+            ),
+            Self::MethodNotAllowed => (
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method_not_allowed",
+                "Method not allowed",
+            ),
+            Self::RateLimited { .. } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "rate_limited",
+                "Too many requests, slow down and retry later",
+            ),
+            Self::PayloadTooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+                "The request body is too large",
+            ),
+            Self::RequestTimeout => (
+                StatusCode::GATEWAY_TIMEOUT,
+                "request_timeout",
+                "The request took too long, please retry",
+            ),
+            Self::Overloaded => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "overloaded",
+                "The server is too busy, please retry",
             ),
-            Self::TaskCreationFailed => (StatusCode::BAD_REQUEST, "Failed to create task"),
-            Self::TaskNotFound => (StatusCode::NOT_FOUND, "Task not found"),
-            Self::ErrorUpdatingTask => (StatusCode::INTERNAL_SERVER_ERROR, "Error updating task"),
-            Self::ErrorDeletingTask => (StatusCode::INTERNAL_SERVER_ERROR, "Error deleting task"),
 
             // --- General ---
-            Self::DatabaseQueryFailed => {
-                (StatusCode::INTERNAL_SERVER_ERROR, "Database query failed")
+            Self::Database { source, .. } => match DatabaseFailure::classify(source) {
+                DatabaseFailure::UniqueViolation => (
+                    StatusCode::CONFLICT,
+                    "conflict",
+                    "The request conflicts with an existing resource",
+                ),
+                DatabaseFailure::ForeignKeyViolation => (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "invalid_reference",
+                    "The request references a resource that does not exist",
+                ),
+                DatabaseFailure::SerializationFailure => (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "concurrent_update",
+                    "The request conflicted with a concurrent update, please retry",
+                ),
+                DatabaseFailure::Unavailable => (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "database_unavailable",
+                    "The database is unavailable, please retry",
+                ),
+                DatabaseFailure::Other => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "database_query_failed",
+                    "Database query failed",
+                ),
+            },
+            Self::NotFound => (StatusCode::NOT_FOUND, "not_found", "Not found"),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> axum::response::Response {
+        let www_authenticate = self.www_authenticate();
+        let retry_after = self.retry_after();
+        let variant = ErrorVariant(self.variant());
+        let (status, code, message) = self.describe();
+
+        // the one place failures with an underlying cause are logged
+        if std::error::Error::source(&self).is_some() {
+            if status.is_server_error() {
+                tracing::error!(code, "{}", self.chain());
+            } else {
+                tracing::warn!(code, "{}", self.chain());
             }
-            Self::NotFound => (StatusCode::NOT_FOUND, "Not found"),
+        }
+
+        let message = match &self {
+            Self::MalformedBody(detail) => format!("{}: {}", message, detail),
+            _ => message.to_string(),
+        };
+        let fields = match self {
+            Self::Validation(fields) => fields,
+            _ => Vec::new(),
         };
 
-        let body = Json(serde_json::json!({
-            "success": false,
-            "error": error_message,
-        }));
+        let mut response = if api::wants_problem_json() {
+            let problem = ProblemDetails {
+                problem_type: "about:blank".to_string(),
+                title: status.canonical_reason().unwrap_or_default().to_string(),
+                status: status.as_u16(),
+                detail: message,
+                code,
+                request_id: telemetry::current_request_id(),
+                errors: fields,
+            };
+            let mut response = (status, Json(problem)).into_response();
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static(api::PROBLEM_JSON));
+            response
+        } else {
+            let error = APIError {
+                code,
+                message,
+                fields,
+            };
+            (status, Json(ErrorResponse::new(error))).into_response()
+        };
+
+        if let Some(challenge) = www_authenticate.and_then(|c| HeaderValue::from_str(&c).ok()) {
+            response.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+        }
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response.extensions_mut().insert(variant);
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(error: AppError) -> Option<String> {
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        response
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn token_failures_carry_a_bearer_challenge() {
+        assert_eq!(
+            challenge(AppError::ExpiredToken).as_deref(),
+            Some(
+                r#"Bearer realm="tasks_backend", error="invalid_token", error_description="The access token expired""#
+            )
+        );
+        assert_eq!(
+            challenge(AppError::TokenNotYetValid).as_deref(),
+            Some(
+                r#"Bearer realm="tasks_backend", error="invalid_token", error_description="The access token is not valid yet""#
+            )
+        );
+        assert_eq!(
+            challenge(AppError::InvalidTokenAudience).as_deref(),
+            Some(
+                r#"Bearer realm="tasks_backend", error="invalid_token", error_description="The access token was issued for another audience""#
+            )
+        );
+    }
 
-        (status, body).into_response()
+    #[test]
+    fn missing_token_gets_a_challenge_without_an_error() {
+        assert_eq!(
+            challenge(AppError::MissingToken).as_deref(),
+            Some(r#"Bearer realm="tasks_backend""#)
+        );
     }
 }
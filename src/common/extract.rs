@@ -0,0 +1,554 @@
+//! Drop-in replacements for axum's `Json`, `Path` and `Query` extractors that
+//! reject with `AppError`, so malformed input gets the same envelope as every
+//! other error instead of axum's plain-text rejections.
+
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Request, rejection::PathRejection},
+    http::{HeaderMap, StatusCode, header::CONTENT_TYPE, request::Parts},
+    response::{IntoResponse, Response},
+};
+use serde::{
+    Serialize,
+    de::{
+        self, DeserializeOwned, Deserializer, IntoDeserializer, Visitor,
+        value::{MapAccessDeserializer, MapDeserializer, SeqDeserializer},
+    },
+    forward_to_deserialize_any,
+};
+use std::fmt;
+
+use super::{api::FieldError, errors::AppError};
+
+/// JSON request body. Syntax errors are `MalformedBody`, well-formed JSON of
+/// the wrong shape is a `Validation` error naming the offending field.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json<T>(pub T);
+
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !is_json(req.headers()) {
+            return Err(AppError::UnsupportedMediaType);
+        }
+
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| match e.status() {
+                StatusCode::PAYLOAD_TOO_LARGE => AppError::PayloadTooLarge,
+                _ => AppError::MalformedBody(e.body_text()),
+            })?;
+
+        // syntax first, then the shape through our own error type so a
+        // missing or mistyped field is known without reading the message
+        let value = serde_json::from_slice::<serde_json::Value>(&bytes)
+            .map_err(|e| AppError::MalformedBody(e.to_string()))?;
+        let value = serde_path_to_error::deserialize(JsonValue(value))
+            .map_err(|e| AppError::Validation(vec![field_error(e)]))?;
+
+        Ok(Json(value))
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+/// Path parameters. A value that doesn't parse, e.g. a bad UUID, is a
+/// `Validation` error keyed by the parameter name.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Path<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for Path<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        use axum::extract::path::ErrorKind;
+
+        match axum::extract::Path::<T>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Path(value)) => Ok(Path(value)),
+            Err(PathRejection::FailedToDeserializePathParams(e)) => {
+                let error = match e.into_kind() {
+                    ErrorKind::ParseErrorAtKey {
+                        key,
+                        value,
+                        expected_type,
+                    } => FieldError::new(key, expected(&value, expected_type)),
+                    ErrorKind::ParseErrorAtIndex {
+                        index,
+                        value,
+                        expected_type,
+                    } => FieldError::new(index.to_string(), expected(&value, expected_type)),
+                    ErrorKind::ParseError {
+                        value,
+                        expected_type,
+                    } => FieldError::new("path", expected(&value, expected_type)),
+                    ErrorKind::DeserializeError { key, message, .. } => {
+                        FieldError::new(key, message)
+                    }
+                    ErrorKind::InvalidUtf8InPathParam { key } => {
+                        FieldError::new(key, "is not valid UTF-8")
+                    }
+                    kind => FieldError::new("path", kind.to_string()),
+                };
+
+                Err(AppError::Validation(vec![error]))
+            }
+            Err(e) => {
+                // a handler asking for parameters its route doesn't have
+                tracing::error!("Path extraction failed: {}", e.body_text());
+                Err(AppError::NotFound)
+            }
+        }
+    }
+}
+
+/// Query string. Unknown enum values and unparsable numbers are `Validation`
+/// errors keyed by the parameter name.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Query<T>(pub T);
+
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let pairs = form_urlencoded::parse(query.as_bytes())
+            .map(|(key, value)| (key.into_owned(), QueryValue(value.into_owned())));
+
+        serde_path_to_error::deserialize(QueryMap(pairs))
+            .map(Query)
+            .map_err(|e| AppError::Validation(vec![field_error(e)]))
+    }
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    let media = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    media == "application/json" || (media.starts_with("application/") && media.ends_with("+json"))
+}
+
+/// What went wrong with one field. Derived `Deserialize` impls report
+/// through the `de::Error` constructors, so implementing them keeps the kind
+/// of failure instead of a message to pick apart.
+#[derive(Debug)]
+enum Issue {
+    Missing(&'static str),
+    Unknown(String),
+    Invalid(String),
+}
+
+impl de::Error for Issue {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        Self::Invalid(message.to_string())
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        Self::Missing(field)
+    }
+
+    fn unknown_field(field: &str, _expected: &'static [&'static str]) -> Self {
+        Self::Unknown(field.to_string())
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(field) => write!(f, "missing field {field}"),
+            Self::Unknown(field) => write!(f, "unknown field {field}"),
+            Self::Invalid(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Issue {}
+
+/// serde reports missing and unknown fields against the parent, move them
+/// onto the field itself.
+fn field_error(error: serde_path_to_error::Error<Issue>) -> FieldError {
+    let path = error.path().to_string();
+    let nested = |name: &str| match path.as_str() {
+        "." => name.to_string(),
+        parent => format!("{}.{}", parent, name),
+    };
+
+    match error.into_inner() {
+        Issue::Missing(name) => FieldError::new(nested(name), "is required"),
+        Issue::Unknown(name) => FieldError::new(nested(&name), "is not a known field"),
+        Issue::Invalid(message) => {
+            let field = match path.as_str() {
+                "." => "body".to_string(),
+                path => path.to_string(),
+            };
+            FieldError::new(field, message)
+        }
+    }
+}
+
+/// A parsed JSON document, deserialized again with [`Issue`] as the error.
+struct JsonValue(serde_json::Value);
+
+impl<'de> IntoDeserializer<'de, Issue> for JsonValue {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for JsonValue {
+    type Error = Issue;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Issue> {
+        use serde_json::Value;
+
+        match self.0 {
+            Value::Null => visitor.visit_unit(),
+            Value::Bool(value) => visitor.visit_bool(value),
+            Value::Number(number) => match (number.as_u64(), number.as_i64(), number.as_f64()) {
+                (Some(value), _, _) => visitor.visit_u64(value),
+                (_, Some(value), _) => visitor.visit_i64(value),
+                (_, _, Some(value)) => visitor.visit_f64(value),
+                _ => Err(de::Error::custom("number out of range")),
+            },
+            Value::String(value) => visitor.visit_string(value),
+            Value::Array(items) => {
+                let mut seq = SeqDeserializer::new(items.into_iter().map(JsonValue));
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            Value::Object(object) => {
+                let mut map =
+                    MapDeserializer::new(object.into_iter().map(|(k, v)| (k, JsonValue(v))));
+                let value = visitor.visit_map(&mut map)?;
+                map.end()?;
+                Ok(value)
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Issue> {
+        match self.0 {
+            serde_json::Value::Null => visitor.visit_none(),
+            value => visitor.visit_some(JsonValue(value)),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Issue> {
+        match self.0 {
+            // unit variants are strings, the rest `{"variant": ...}`
+            serde_json::Value::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+            serde_json::Value::Object(object) => visitor.visit_enum(MapAccessDeserializer::new(
+                MapDeserializer::new(object.into_iter().map(|(k, v)| (k, JsonValue(v)))),
+            )),
+            _ => Err(de::Error::custom("expected a string or an object")),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Issue> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+/// The decoded `key=value` pairs of a query string.
+struct QueryMap<I>(I);
+
+impl<'de, I> Deserializer<'de> for QueryMap<I>
+where
+    I: Iterator<Item = (String, QueryValue)>,
+{
+    type Error = Issue;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Issue> {
+        let mut map = MapDeserializer::new(self.0);
+        let value = visitor.visit_map(&mut map)?;
+        map.end()?;
+        Ok(value)
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// One query string value, parsed into whatever type the field asks for.
+struct QueryValue(String);
+
+impl<'de> IntoDeserializer<'de, Issue> for QueryValue {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! parse_query_value {
+    ($($method:ident => $visit:ident($ty:ty)),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Issue> {
+                match self.0.parse::<$ty>() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(_) => Err(Issue::Invalid(expected(&self.0, stringify!($ty)))),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for QueryValue {
+    type Error = Issue;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Issue> {
+        visitor.visit_string(self.0)
+    }
+
+    // present in the query means present, even when empty
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Issue> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Issue> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Issue> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    parse_query_value! {
+        deserialize_bool => visit_bool(bool),
+        deserialize_i8 => visit_i8(i8),
+        deserialize_i16 => visit_i16(i16),
+        deserialize_i32 => visit_i32(i32),
+        deserialize_i64 => visit_i64(i64),
+        deserialize_u8 => visit_u8(u8),
+        deserialize_u16 => visit_u16(u16),
+        deserialize_u32 => visit_u32(u32),
+        deserialize_u64 => visit_u64(u64),
+        deserialize_f32 => visit_f32(f32),
+        deserialize_f64 => visit_f64(f64),
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+fn expected(value: &str, expected_type: &str) -> String {
+    let type_name = expected_type.rsplit("::").next().unwrap_or(expected_type);
+    format!("{:?} is not a valid {}", value, type_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::api::{APIResponse, AppResponse};
+    use axum::{
+        Router,
+        body::{Body, to_bytes},
+        routing::{get, post},
+    };
+    use serde::Deserialize;
+    use serde_json::{Value, json};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    #[derive(Debug, Deserialize)]
+    struct Item {
+        #[allow(dead_code)]
+        name: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Payload {
+        title: String,
+        #[allow(dead_code)]
+        count: Option<i64>,
+        #[allow(dead_code)]
+        #[serde(default)]
+        items: Vec<Item>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Page {
+        limit: Option<i64>,
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/items",
+                post(|Json(payload): Json<Payload>| async move {
+                    let response: AppResponse<String> = Ok(APIResponse::success(payload.title));
+                    response
+                }),
+            )
+            .route(
+                "/items/{id}",
+                get(|Path(id): Path<Uuid>| async move { id.to_string() }),
+            )
+            .route(
+                "/page",
+                get(|Query(page): Query<Page>| async move { format!("{:?}", page.limit) }),
+            )
+    }
+
+    async fn send(request: axum::http::Request<Body>) -> (StatusCode, Value) {
+        let response = app().oneshot(request).await.expect("infallible");
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body reads");
+
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    async fn post_json(body: &str) -> (StatusCode, Value) {
+        let request = axum::http::Request::post("/items")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .expect("valid request");
+
+        send(request).await
+    }
+
+    async fn get_uri(uri: &str) -> (StatusCode, Value) {
+        send(
+            axum::http::Request::get(uri)
+                .body(Body::empty())
+                .expect("valid request"),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn valid_body_gets_the_envelope() {
+        let (status, body) = post_json(r#"{"title": "write tests", "count": 2}"#).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["response_message"], "success");
+        assert_eq!(body["response_data"], "write tests");
+    }
+
+    #[tokio::test]
+    async fn malformed_json_is_a_bad_request() {
+        let (status, body) = post_json(r#"{"title": "#).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"]["code"], "malformed_body");
+        assert_eq!(body["response_data"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn missing_field_is_reported_on_the_field() {
+        let (status, body) = post_json(r#"{"count": 2}"#).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["code"], "validation_failed");
+        assert_eq!(
+            body["error"]["fields"],
+            json!([{"field": "title", "message": "is required"}])
+        );
+    }
+
+    #[tokio::test]
+    async fn nested_missing_field_gets_its_full_path() {
+        let (status, body) = post_json(r#"{"title": "t", "items": [{"name": "a"}, {}]}"#).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["fields"][0]["field"], "items[1].name");
+    }
+
+    #[tokio::test]
+    async fn wrong_type_names_the_field() {
+        let (status, body) = post_json(r#"{"title": "t", "count": "two"}"#).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["fields"][0]["field"], "count");
+    }
+
+    #[tokio::test]
+    async fn wrong_content_type_is_rejected() {
+        let request = axum::http::Request::post("/items")
+            .header(CONTENT_TYPE, "text/plain")
+            .body(Body::from(r#"{"title": "t"}"#))
+            .expect("valid request");
+
+        let (status, body) = send(request).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body["error"]["code"], "unsupported_media_type");
+    }
+
+    #[tokio::test]
+    async fn bad_path_param_names_the_param() {
+        let (status, body) = get_uri("/items/not-a-uuid").await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["fields"][0]["field"], "id");
+    }
+
+    #[tokio::test]
+    async fn query_values_are_parsed_per_field() {
+        let (status, _) = get_uri("/page?limit=5").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = get_uri("/page?limit=five").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            body["error"]["fields"],
+            json!([{"field": "limit", "message": "\"five\" is not a valid i64"}])
+        );
+    }
+}
@@ -1,21 +1,261 @@
-use crate::{config::JWTConfig, models};
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
-use serde::{Deserialize, Serialize};
+use crate::{common::errors::AppError, config::JWTConfig, models::user::Role};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation, decode, decode_header,
+    encode,
+    errors::ErrorKind,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+};
+use rsa::{RsaPublicKey, pkcs8::DecodePublicKey, traits::PublicKeyParts};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{collections::HashMap, fmt};
+use uuid::Uuid;
 
 use chrono::{Duration, Utc};
 
+// DER prefix of an Ed25519 SubjectPublicKeyInfo, the raw 32 byte key follows it
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Claims {
     pub user_id: i64,
     pub username: String,
+    pub role: Role,
+    pub iss: String,
+    pub aud: String,
     pub exp: usize,
+    pub nbf: usize,
     pub iat: usize,
+    pub jti: String,
+    // the login session this token belongs to, see `SessionService`
+    pub sid: Uuid,
+}
+
+#[derive(Debug)]
+pub enum JwtKeyError {
+    MissingSigningKey,
+    UnreadableKey(String, std::io::Error),
+    InvalidKey(String),
+    // the signing kid has no public key in `verification_keys`
+    SigningKeyNotVerifiable(String),
+}
+
+impl fmt::Display for JwtKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingSigningKey => {
+                write!(f, "JWT_SIGNING_KEY_PATH must be set for RS256/EdDSA")
+            }
+            Self::UnreadableKey(path, e) => write!(f, "could not read key file {}: {}", path, e),
+            Self::InvalidKey(kid) => {
+                write!(f, "key '{}' is not a valid RSA or Ed25519 PEM key", kid)
+            }
+            Self::SigningKeyNotVerifiable(kid) => write!(
+                f,
+                "signing key '{}' needs its public key in JWT_VERIFICATION_KEYS",
+                kid
+            ),
+        }
+    }
+}
+
+impl std::error::Error for JwtKeyError {}
+
+/// Key material loaded once at startup from [`JWTConfig`].
+///
+/// New tokens are signed with a single key and carry its `kid`, while any key
+/// in the verification set is accepted so keys can be rotated without logging
+/// everyone out.
+#[derive(Clone)]
+pub struct JwtKeys {
+    algorithm: Algorithm,
+    signing_kid: String,
+    encoding_key: EncodingKey,
+    decoding_keys: HashMap<String, (Algorithm, DecodingKey)>,
+    jwks: JwkSet,
+    issuer: String,
+    audience: String,
+    leeway: u64,
+}
+
+impl fmt::Debug for JwtKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtKeys")
+            .field("algorithm", &self.algorithm)
+            .field("signing_kid", &self.signing_kid)
+            .field("verification_kids", &self.decoding_keys.keys())
+            .field("issuer", &self.issuer)
+            .field("audience", &self.audience)
+            .finish()
+    }
+}
+
+impl JwtKeys {
+    pub fn from_config(config: &JWTConfig) -> Result<Self, JwtKeyError> {
+        let signing_kid = config.signing_key_id.clone();
+
+        if config.algorithm == Algorithm::HS256 {
+            let mut decoding_keys = HashMap::new();
+            decoding_keys.insert(
+                signing_kid.clone(),
+                (
+                    Algorithm::HS256,
+                    DecodingKey::from_secret(config.secret.as_bytes()),
+                ),
+            );
+
+            // a shared secret must never be published, so the JWKS stays empty
+            return Ok(Self {
+                algorithm: Algorithm::HS256,
+                signing_kid,
+                encoding_key: EncodingKey::from_secret(config.secret.as_bytes()),
+                decoding_keys,
+                jwks: JwkSet { keys: Vec::new() },
+                issuer: config.issuer.clone(),
+                audience: config.audience.clone(),
+                leeway: config.leeway,
+            });
+        }
+
+        let signing_key_path = config
+            .signing_key_path
+            .as_ref()
+            .ok_or(JwtKeyError::MissingSigningKey)?;
+        let signing_pem = read_key_file(signing_key_path)?;
+
+        let encoding_key = match config.algorithm {
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(&signing_pem),
+            _ => EncodingKey::from_rsa_pem(&signing_pem),
+        }
+        .map_err(|_| JwtKeyError::InvalidKey(signing_kid.clone()))?;
+
+        let mut decoding_keys = HashMap::new();
+        let mut jwks = JwkSet { keys: Vec::new() };
+
+        for (kid, path) in &config.verification_keys {
+            let jwk = public_key_to_jwk(kid, &read_key_file(path)?)?;
+            let algorithm = match jwk.algorithm {
+                AlgorithmParameters::OctetKeyPair(_) => Algorithm::EdDSA,
+                _ => Algorithm::RS256,
+            };
+            let decoding_key =
+                DecodingKey::from_jwk(&jwk).map_err(|_| JwtKeyError::InvalidKey(kid.clone()))?;
+
+            decoding_keys.insert(kid.clone(), (algorithm, decoding_key));
+            jwks.keys.push(jwk);
+        }
+
+        match decoding_keys.get(&signing_kid) {
+            Some((algorithm, _)) if *algorithm == config.algorithm => {}
+            _ => return Err(JwtKeyError::SigningKeyNotVerifiable(signing_kid)),
+        }
+
+        Ok(Self {
+            algorithm: config.algorithm,
+            signing_kid,
+            encoding_key,
+            decoding_keys,
+            jwks,
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+            leeway: config.leeway,
+        })
+    }
+
+    /// Public keys for `/.well-known/jwks.json`.
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+
+    fn header(&self) -> Header {
+        let mut header = Header::new(self.algorithm);
+        header.kid = Some(self.signing_kid.clone());
+        header
+    }
+
+    fn decode<T: DeserializeOwned + Clone>(
+        &self,
+        token: &str,
+    ) -> Result<TokenData<T>, jsonwebtoken::errors::Error> {
+        let header = decode_header(token)?;
+
+        // tokens issued before kids were introduced fall back to the signing key
+        let kid = header.kid.as_deref().unwrap_or(&self.signing_kid);
+        let (algorithm, key) = self
+            .decoding_keys
+            .get(kid)
+            .ok_or(jsonwebtoken::errors::Error::from(ErrorKind::InvalidToken))?;
+
+        decode::<T>(token, key, &self.validation(*algorithm))
+    }
+
+    fn validation(&self, algorithm: Algorithm) -> Validation {
+        // pinning the algorithm per key blocks alg confusion attacks
+        let mut validation = Validation::new(algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud"]);
+        validation.validate_nbf = true;
+        validation.leeway = self.leeway;
+        validation
+    }
+}
+
+fn read_key_file(path: &str) -> Result<Vec<u8>, JwtKeyError> {
+    std::fs::read(path).map_err(|e| JwtKeyError::UnreadableKey(path.to_string(), e))
+}
+
+fn public_key_to_jwk(kid: &str, pem_bytes: &[u8]) -> Result<Jwk, JwtKeyError> {
+    let invalid = || JwtKeyError::InvalidKey(kid.to_string());
+    let pem_str = std::str::from_utf8(pem_bytes).map_err(|_| invalid())?;
+    let der = pem::parse(pem_str).map_err(|_| invalid())?;
+
+    let (key_algorithm, algorithm) = match der.contents().strip_prefix(&ED25519_SPKI_PREFIX) {
+        Some(raw) if raw.len() == 32 => (
+            KeyAlgorithm::EdDSA,
+            AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                key_type: OctetKeyPairType::OctetKeyPair,
+                curve: EllipticCurve::Ed25519,
+                x: URL_SAFE_NO_PAD.encode(raw),
+            }),
+        ),
+        _ => {
+            let public_key = RsaPublicKey::from_public_key_pem(pem_str).map_err(|_| invalid())?;
+
+            (
+                KeyAlgorithm::RS256,
+                AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be()),
+                    e: URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+                }),
+            )
+        }
+    };
+
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(key_algorithm),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm,
+    })
 }
 
 pub fn generate_token(
     username: &str,
     user_id: i64,
+    role: Role,
+    session_id: Uuid,
     config: &JWTConfig,
+    keys: &JwtKeys,
 ) -> Result<String, jsonwebtoken::errors::Error> {
     let now = Utc::now();
     let exp = (now + Duration::seconds(config.expiration)).timestamp() as usize;
@@ -24,27 +264,324 @@ pub fn generate_token(
     let claims = Claims {
         user_id,
         username: username.to_string(),
+        role,
+        iss: keys.issuer.clone(),
+        aud: keys.audience.clone(),
         exp,
+        nbf: iat,
         iat,
+        jti: Uuid::new_v4().to_string(),
+        sid: session_id,
+    };
+
+    encode(&keys.header(), &claims, &keys.encoding_key)
+}
+
+pub fn verify_token(token: &str, keys: &JwtKeys) -> Result<Claims, jsonwebtoken::errors::Error> {
+    let decoded = keys.decode::<Claims>(token)?;
+
+    Ok(decoded.claims)
+}
+
+/// Maps a verification failure onto the reason we report to the client.
+pub fn rejection_reason(error: &jsonwebtoken::errors::Error) -> AppError {
+    match error.kind() {
+        ErrorKind::ExpiredSignature => AppError::ExpiredToken,
+        ErrorKind::ImmatureSignature => AppError::TokenNotYetValid,
+        ErrorKind::InvalidIssuer => AppError::InvalidTokenIssuer,
+        ErrorKind::InvalidAudience => AppError::InvalidTokenAudience,
+        _ => AppError::InvalidToken,
+    }
+}
+
+const MFA_PENDING_PURPOSE: &str = "mfa_pending";
+
+/// Claims of the short-lived token handed out after a correct password when
+/// the user still has to present a second factor. It deliberately has no
+/// `username`, so it never decodes as regular [`Claims`].
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MfaPendingClaims {
+    pub user_id: i64,
+    pub purpose: String,
+    pub iss: String,
+    pub aud: String,
+    pub exp: usize,
+    pub nbf: usize,
+    pub iat: usize,
+    pub jti: String,
+}
+
+/// `challenge_id` becomes the `jti`, it names the row that counts the
+/// attempts made with this token.
+pub fn generate_mfa_pending_token(
+    user_id: i64,
+    challenge_id: Uuid,
+    expiration: i64,
+    keys: &JwtKeys,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = Utc::now();
+
+    let claims = MfaPendingClaims {
+        user_id,
+        purpose: MFA_PENDING_PURPOSE.to_string(),
+        iss: keys.issuer.clone(),
+        aud: keys.audience.clone(),
+        exp: (now + Duration::seconds(expiration)).timestamp() as usize,
+        nbf: now.timestamp() as usize,
+        iat: now.timestamp() as usize,
+        jti: challenge_id.to_string(),
     };
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(config.secret.as_bytes()),
-    )
+    encode(&keys.header(), &claims, &keys.encoding_key)
 }
 
-pub fn verify_token(
+pub fn verify_mfa_pending_token(
     token: &str,
-    config: &JWTConfig,
-) -> Result<Claims, jsonwebtoken::errors::Error> {
-    let config_secret = config.secret.as_bytes();
-    let decoded = decode::<Claims>(
-        &token,
-        &DecodingKey::from_secret(config_secret),
-        &Validation::new(Algorithm::HS256),
-    )?;
+    keys: &JwtKeys,
+) -> Result<MfaPendingClaims, jsonwebtoken::errors::Error> {
+    let decoded = keys.decode::<MfaPendingClaims>(token)?;
+
+    if decoded.claims.purpose != MFA_PENDING_PURPOSE {
+        return Err(ErrorKind::InvalidToken.into());
+    }
 
     Ok(decoded.claims)
 }
+
+const OIDC_STATE_PURPOSE: &str = "oidc_state";
+
+/// Claims of the cookie that ties an OIDC `state` to the browser that
+/// started the sign in, so a callback URL can't be replayed in another one.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OidcStateClaims {
+    pub state: String,
+    pub purpose: String,
+    pub iss: String,
+    pub aud: String,
+    pub exp: usize,
+    pub nbf: usize,
+    pub iat: usize,
+}
+
+pub fn generate_oidc_state_token(
+    state: &str,
+    expiration: i64,
+    keys: &JwtKeys,
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = Utc::now();
+
+    let claims = OidcStateClaims {
+        state: state.to_string(),
+        purpose: OIDC_STATE_PURPOSE.to_string(),
+        iss: keys.issuer.clone(),
+        aud: keys.audience.clone(),
+        exp: (now + Duration::seconds(expiration)).timestamp() as usize,
+        nbf: now.timestamp() as usize,
+        iat: now.timestamp() as usize,
+    };
+
+    encode(&keys.header(), &claims, &keys.encoding_key)
+}
+
+pub fn verify_oidc_state_token(
+    token: &str,
+    keys: &JwtKeys,
+) -> Result<OidcStateClaims, jsonwebtoken::errors::Error> {
+    let decoded = keys.decode::<OidcStateClaims>(token)?;
+
+    if decoded.claims.purpose != OIDC_STATE_PURPOSE {
+        return Err(ErrorKind::InvalidToken.into());
+    }
+
+    Ok(decoded.claims)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/jwt/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    // signs with `kid`'s private key, verifies with every listed public key
+    fn keys(algorithm: &str, kid: &str, verification_keys: &[&str]) -> JwtKeys {
+        let signing_key = fixture(&format!("{kid}.pem"));
+        let verification_keys = verification_keys
+            .iter()
+            .map(|kid| format!("{kid}={}", fixture(&format!("{kid}.pub.pem"))))
+            .collect::<Vec<_>>()
+            .join(",");
+        let config = test_support::config(&[
+            ("JWT_ALGORITHM", algorithm),
+            ("JWT_SIGNING_KEY_ID", kid),
+            ("JWT_SIGNING_KEY_PATH", &signing_key),
+            ("JWT_VERIFICATION_KEYS", &verification_keys),
+        ]);
+
+        JwtKeys::from_config(&config.jwt_config).expect("valid keys")
+    }
+
+    fn token(keys: &JwtKeys) -> String {
+        let config = test_support::config(&[]);
+
+        generate_token(
+            "alice",
+            7,
+            Role::User,
+            Uuid::new_v4(),
+            &config.jwt_config,
+            keys,
+        )
+        .expect("signs")
+    }
+
+    #[test]
+    fn rs256_and_eddsa_tokens_round_trip() {
+        for keys in [
+            keys("RS256", "rs256_current", &["rs256_current"]),
+            keys("EdDSA", "ed25519", &["ed25519"]),
+        ] {
+            let claims = verify_token(&token(&keys), &keys).expect("verifies");
+            assert_eq!(claims.user_id, 7);
+            assert_eq!(claims.username, "alice");
+        }
+    }
+
+    #[test]
+    fn retired_keys_still_verify_until_removed() {
+        let retired = keys("RS256", "rs256_retired", &["rs256_retired"]);
+        let old_token = token(&retired);
+
+        let rotated = keys(
+            "RS256",
+            "rs256_current",
+            &["rs256_current", "rs256_retired"],
+        );
+        assert!(verify_token(&old_token, &rotated).is_ok());
+
+        let cleaned_up = keys("RS256", "rs256_current", &["rs256_current"]);
+        let error = verify_token(&old_token, &cleaned_up).expect_err("unknown kid");
+        assert_eq!(error.kind(), &ErrorKind::InvalidToken);
+    }
+
+    #[test]
+    fn hs256_signed_with_the_public_key_is_refused() {
+        let keys = keys("RS256", "rs256_current", &["rs256_current"]);
+        let claims = verify_token(&token(&keys), &keys).expect("verifies");
+
+        // the classic confusion attack, the public key used as an HMAC secret
+        let public_pem = std::fs::read(fixture("rs256_current.pub.pem")).unwrap();
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some("rs256_current".to_string());
+        let forged = encode(&header, &claims, &EncodingKey::from_secret(&public_pem)).unwrap();
+
+        let error = verify_token(&forged, &keys).expect_err("algorithm is pinned per key");
+        assert_eq!(error.kind(), &ErrorKind::InvalidAlgorithm);
+    }
+
+    #[test]
+    fn jwks_lists_every_verification_key() {
+        let keys = keys(
+            "RS256",
+            "rs256_current",
+            &["rs256_current", "rs256_retired", "ed25519"],
+        );
+        let expected: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(fixture("jwks.json")).unwrap()).unwrap();
+
+        assert_eq!(serde_json::to_value(keys.jwks()).unwrap(), expected);
+    }
+
+    // HS256 test keys, issuer and audience default to tasks_backend, leeway to 60s
+    fn claims(now: i64) -> serde_json::Value {
+        serde_json::json!({
+            "user_id": 7,
+            "username": "alice",
+            "role": "user",
+            "iss": "tasks_backend",
+            "aud": "tasks_backend",
+            "exp": now + 3600,
+            "nbf": now,
+            "iat": now,
+            "jti": Uuid::new_v4().to_string(),
+            "sid": Uuid::new_v4(),
+        })
+    }
+
+    fn check(claims: &serde_json::Value) -> Result<Claims, AppError> {
+        let keys = JwtKeys::from_config(&test_support::config(&[]).jwt_config).unwrap();
+        let token = encode(&keys.header(), claims, &keys.encoding_key).unwrap();
+
+        verify_token(&token, &keys).map_err(|e| rejection_reason(&e))
+    }
+
+    #[test]
+    fn issuer_and_audience_must_match() {
+        let now = Utc::now().timestamp();
+        assert!(check(&claims(now)).is_ok());
+
+        let mut claims = self::claims(now);
+        claims["aud"] = "another_service".into();
+        assert!(matches!(
+            check(&claims),
+            Err(AppError::InvalidTokenAudience)
+        ));
+
+        let mut claims = self::claims(now);
+        claims["iss"] = "https://evil.example".into();
+        assert!(matches!(check(&claims), Err(AppError::InvalidTokenIssuer)));
+    }
+
+    #[test]
+    fn exp_and_nbf_allow_the_leeway_and_no_more() {
+        let now = Utc::now().timestamp();
+        let with = |key: &str, value: i64| {
+            let mut claims = claims(now);
+            claims[key] = value.into();
+            check(&claims)
+        };
+
+        assert!(with("exp", now - 30).is_ok());
+        assert!(matches!(
+            with("exp", now - 120),
+            Err(AppError::ExpiredToken)
+        ));
+
+        assert!(with("nbf", now + 30).is_ok());
+        assert!(matches!(
+            with("nbf", now + 120),
+            Err(AppError::TokenNotYetValid)
+        ));
+    }
+
+    #[test]
+    fn iss_aud_and_nbf_are_required() {
+        let keys = JwtKeys::from_config(&test_support::config(&[]).jwt_config).unwrap();
+
+        for claim in ["iss", "aud", "nbf", "exp"] {
+            let mut claims = claims(Utc::now().timestamp());
+            claims.as_object_mut().unwrap().remove(claim);
+            let token = encode(&keys.header(), &claims, &keys.encoding_key).unwrap();
+
+            // decoded loosely, so it is the validation and not serde refusing it
+            let error = keys
+                .decode::<serde_json::Value>(&token)
+                .expect_err("claim is required");
+            assert!(
+                matches!(error.kind(), ErrorKind::MissingRequiredClaim(missing) if missing == claim),
+                "{claim}: {error:?}"
+            );
+            assert!(matches!(rejection_reason(&error), AppError::InvalidToken));
+        }
+    }
+
+    #[test]
+    fn hs256_publishes_nothing() {
+        let keys = JwtKeys::from_config(&test_support::config(&[]).jwt_config).unwrap();
+
+        assert!(keys.jwks().keys.is_empty());
+        assert!(verify_token(&token(&keys), &keys).is_ok());
+    }
+}
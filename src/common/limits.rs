@@ -0,0 +1,142 @@
+//! Keeps a slow database or a flood of requests from taking the server down:
+//! bodies are bounded, every request has a deadline and past a number of
+//! requests in flight new ones are shed instead of queued.
+
+use axum::{Router, extract::DefaultBodyLimit, middleware as axum_middleware};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Semaphore;
+
+use crate::{config::LimitsConfig, middleware::middleware_limits};
+
+#[derive(Debug)]
+pub struct RequestLimits {
+    pub in_flight: Semaphore,
+    pub request_timeout: Duration,
+}
+
+/// Puts every route of `router` under the limits in `config`.
+pub fn apply<S>(router: Router<S>, config: &LimitsConfig) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let limits = Arc::new(RequestLimits {
+        in_flight: Semaphore::new(config.max_in_flight),
+        request_timeout: config.request_timeout,
+    });
+
+    router
+        .layer(axum_middleware::from_fn_with_state(
+            limits,
+            middleware_limits,
+        ))
+        .layer(DefaultBodyLimit::max(config.max_body_bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::apply;
+    use crate::{common::extract::Json, config::LimitsConfig};
+    use axum::{
+        Router,
+        body::{Body, to_bytes},
+        http::{Request, StatusCode, header::RETRY_AFTER},
+        routing::{get, post},
+    };
+    use std::{sync::Arc, time::Duration};
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let response = app.clone().oneshot(request).await.expect("infallible");
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body reads");
+
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    fn post_json(len: usize) -> Request<Body> {
+        let body = serde_json::to_vec(&"x".repeat(len)).expect("serializes");
+        Request::post("/echo")
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .expect("valid request")
+    }
+
+    fn echo_app(config: &LimitsConfig) -> Router {
+        let echo = post(|Json(value): Json<serde_json::Value>| async move { Json(value) });
+        apply(Router::new().route("/echo", echo), config)
+    }
+
+    #[tokio::test]
+    async fn bodies_over_the_default_limit_get_413() {
+        let config = LimitsConfig::default();
+        let app = echo_app(&config);
+
+        let (status, _) = send(&app, post_json(1024)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, body) = send(&app, post_json(config.max_body_bytes)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["error"]["code"], "payload_too_large");
+    }
+
+    #[tokio::test]
+    async fn requests_past_the_timeout_get_504() {
+        let config = LimitsConfig {
+            request_timeout: Duration::from_millis(50),
+            ..LimitsConfig::default()
+        };
+        let slow = get(|| async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            "done"
+        });
+        let app = apply(Router::new().route("/slow", slow), &config);
+
+        let request = Request::get("/slow").body(Body::empty()).expect("valid");
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(body["error"]["code"], "request_timeout");
+    }
+
+    #[tokio::test]
+    async fn requests_past_max_in_flight_are_shed_with_503() {
+        let config = LimitsConfig {
+            max_in_flight: 1,
+            ..LimitsConfig::default()
+        };
+        let started = Arc::new(Notify::new());
+        let release = Arc::new(Notify::new());
+        let hold = get({
+            let (started, release) = (started.clone(), release.clone());
+            move || async move {
+                started.notify_one();
+                release.notified().await;
+                "done"
+            }
+        });
+        let app = apply(Router::new().route("/hold", hold), &config);
+        let request = || Request::get("/hold").body(Body::empty()).expect("valid");
+
+        let first = tokio::spawn(app.clone().oneshot(request()));
+        started.notified().await;
+
+        let response = app.clone().oneshot(request()).await.expect("infallible");
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+
+        release.notify_one();
+        let first = first.await.expect("joins").expect("infallible");
+        assert_eq!(first.status(), StatusCode::OK);
+
+        // the permit is back once the first request is done
+        let next = tokio::spawn(app.clone().oneshot(request()));
+        started.notified().await;
+        release.notify_one();
+        assert_eq!(
+            next.await.expect("joins").expect("infallible").status(),
+            StatusCode::OK
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::models::audit::{AuditEventType, AuditOutcome};
+
+/// Prometheus metrics for `/metrics`. Request metrics are recorded by
+/// `middleware_metrics`, the pool gauges are refreshed on scrape and the
+/// task gauges by a background worker.
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub errors: IntCounterVec,
+    pub logins: IntCounterVec,
+    pub pool_connections: IntGaugeVec,
+    pub pool_max_connections: IntGauge,
+    pub pool_acquire_wait: Histogram,
+    pub tasks: IntGaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("tasks_backend".to_string()), None)?;
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by matched route"),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by matched route",
+            ),
+            &["method", "route"],
+        )?;
+        let errors = IntCounterVec::new(
+            Opts::new("errors_total", "Error responses by AppError variant"),
+            &["variant"],
+        )?;
+        let logins = IntCounterVec::new(
+            Opts::new("logins_total", "Sign in attempts by method and outcome"),
+            &["method", "outcome"],
+        )?;
+        let pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database pool connections by state"),
+            &["state"],
+        )?;
+        let pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Configured database pool size limit",
+        )?;
+        let pool_acquire_wait = Histogram::with_opts(
+            HistogramOpts::new(
+                "db_pool_acquire_wait_seconds",
+                "Time a periodic probe waited for a pool connection, rises with pool contention",
+            )
+            .buckets(vec![0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0]),
+        )?;
+        let tasks = IntGaugeVec::new(
+            Opts::new("tasks", "Tasks by status, over all users"),
+            &["status"],
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(errors.clone()))?;
+        registry.register(Box::new(logins.clone()))?;
+        registry.register(Box::new(pool_connections.clone()))?;
+        registry.register(Box::new(pool_max_connections.clone()))?;
+        registry.register(Box::new(pool_acquire_wait.clone()))?;
+        registry.register(Box::new(tasks.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            errors,
+            logins,
+            pool_connections,
+            pool_max_connections,
+            pool_acquire_wait,
+            tasks,
+        })
+    }
+
+    /// Counts sign in attempts, called for every audit event.
+    pub fn record_audit_event(&self, event_type: AuditEventType, outcome: AuditOutcome) {
+        let method = match event_type {
+            AuditEventType::Login => "password",
+            AuditEventType::MfaLogin => "mfa",
+            AuditEventType::OidcLogin => "oidc",
+            _ => return,
+        };
+        let outcome = match outcome {
+            AuditOutcome::Success => "success",
+            AuditOutcome::Failure => "failure",
+        };
+
+        self.logins.with_label_values(&[method, outcome]).inc();
+    }
+
+    /// Everything in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            tracing::error!("Failed to encode metrics: {:?}", e);
+        }
+
+        String::from_utf8_lossy(&buffer).into_owned()
+    }
+}
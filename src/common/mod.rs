@@ -1,4 +1,15 @@
 pub mod api;
+pub mod client_ip;
+pub mod cors;
 pub mod errors;
+pub mod extract;
 pub mod jwt;
+pub mod limits;
+pub mod metrics;
+pub mod oidc;
+pub mod rate_limit;
+pub mod redact;
+pub mod shutdown;
+pub mod telemetry;
+pub mod totp;
 pub mod utils;
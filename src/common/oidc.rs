@@ -0,0 +1,285 @@
+use crate::{
+    common::{errors::AppError, telemetry},
+    config::OidcConfig,
+};
+use axum::http::{HeaderMap, header::COOKIE};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header, jwk::JwkSet};
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Carries the signed `state` from the start of a sign in to the callback.
+pub const STATE_COOKIE: &str = "oidc_state";
+const ID_TOKEN_LEEWAY: u64 = 60;
+
+// signed with the provider's private keys, never with our client secret
+const ALLOWED_ID_TOKEN_ALGORITHMS: [Algorithm; 7] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// The parts of the provider's discovery document we rely on.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    pub preferred_username: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Authorization code + PKCE client for a single OpenID Connect provider.
+///
+/// Discovery is fetched lazily on first use and cached, provider keys are
+/// fetched on every code exchange so key rotation at the provider just works.
+#[derive(Debug)]
+pub struct OidcClient {
+    config: OidcConfig,
+    http: reqwest::Client,
+    metadata: OnceCell<ProviderMetadata>,
+}
+
+impl OidcClient {
+    pub fn new(config: OidcConfig) -> Result<Self, reqwest::Error> {
+        let http = reqwest::Client::builder().timeout(HTTP_TIMEOUT).build()?;
+
+        Ok(Self {
+            config,
+            http,
+            metadata: OnceCell::new(),
+        })
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    /// `Set-Cookie` value for the state cookie, scoped to the callback path
+    /// and `Secure` whenever the callback is served over https.
+    pub fn state_cookie(&self, value: &str, max_age: i64) -> String {
+        let redirect_uri = Url::parse(&self.config.redirect_uri).ok();
+        let path = redirect_uri.as_ref().map_or("/", |url| url.path());
+        let secure = redirect_uri
+            .as_ref()
+            .is_some_and(|url| url.scheme() == "https");
+
+        format!(
+            "{}={}; Max-Age={}; Path={}; HttpOnly; SameSite=Lax{}",
+            STATE_COOKIE,
+            value,
+            max_age,
+            path,
+            if secure { "; Secure" } else { "" }
+        )
+    }
+
+    /// S256 code challenge for a PKCE verifier.
+    pub fn code_challenge(code_verifier: &str) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+    }
+
+    pub async fn metadata(&self) -> Result<&ProviderMetadata, AppError> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer_url
+                );
+                let metadata: ProviderMetadata = self.get_json(&url).await?;
+
+                // the discovery document must describe the issuer we were configured with
+                if metadata.issuer.trim_end_matches('/') != self.config.issuer_url {
+                    tracing::error!("OIDC discovery issuer mismatch: {}", metadata.issuer);
+                    return Err(AppError::OidcProviderError);
+                }
+
+                Ok(metadata)
+            })
+            .await
+    }
+
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_verifier: &str,
+    ) -> Result<String, AppError> {
+        let metadata = self.metadata().await?;
+        let code_challenge = Self::code_challenge(code_verifier);
+
+        let url = Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", self.config.client_id.as_str()),
+                ("redirect_uri", self.config.redirect_uri.as_str()),
+                ("scope", self.config.scopes.as_str()),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", code_challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| {
+            tracing::error!("Invalid OIDC authorization endpoint: {:?}", e);
+            AppError::OidcProviderError
+        })?;
+
+        Ok(url.to_string())
+    }
+
+    /// Exchanges an authorization code and returns the validated ID token claims.
+    pub async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, AppError> {
+        let metadata = self.metadata().await?;
+
+        let mut request = self
+            .http
+            .post(&metadata.token_endpoint)
+            .headers(telemetry::trace_headers())
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", self.config.redirect_uri.as_str()),
+                ("client_id", self.config.client_id.as_str()),
+                ("code_verifier", code_verifier),
+            ]);
+
+        if let Some(secret) = &self.config.client_secret {
+            request = request.basic_auth(&self.config.client_id, Some(secret));
+        }
+
+        let response = request.send().await.map_err(|e| {
+            tracing::error!("OIDC token request failed: {:?}", e);
+            AppError::OidcProviderError
+        })?;
+
+        if !response.status().is_success() {
+            tracing::warn!(
+                "OIDC provider rejected code exchange: {}",
+                response.status()
+            );
+            return Err(AppError::OidcLoginFailed);
+        }
+
+        let tokens: TokenResponse = response.json().await.map_err(|e| {
+            tracing::error!("OIDC token response unreadable: {:?}", e);
+            AppError::OidcProviderError
+        })?;
+
+        self.validate_id_token(metadata, &tokens.id_token, nonce)
+            .await
+    }
+
+    async fn validate_id_token(
+        &self,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, AppError> {
+        let header = decode_header(id_token).map_err(|e| {
+            tracing::warn!("Malformed ID token: {:?}", e);
+            AppError::OidcLoginFailed
+        })?;
+
+        if !ALLOWED_ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            tracing::warn!("ID token signed with disallowed algorithm {:?}", header.alg);
+            return Err(AppError::OidcLoginFailed);
+        }
+
+        let jwks: JwkSet = self.get_json(&metadata.jwks_uri).await?;
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            // without a kid only an unambiguous key set is usable
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        }
+        .ok_or_else(|| {
+            tracing::warn!("No provider key matches ID token kid {:?}", header.kid);
+            AppError::OidcLoginFailed
+        })?;
+
+        let key = DecodingKey::from_jwk(jwk).map_err(|e| {
+            tracing::error!("Unusable provider key: {:?}", e);
+            AppError::OidcProviderError
+        })?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        validation.leeway = ID_TOKEN_LEEWAY;
+
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| {
+                tracing::warn!("ID token validation failed: {:?}", e);
+                AppError::OidcLoginFailed
+            })?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            tracing::warn!("ID token nonce mismatch");
+            return Err(AppError::OidcLoginFailed);
+        }
+
+        Ok(claims)
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, AppError> {
+        self.http
+            .get(url)
+            .headers(telemetry::trace_headers())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| {
+                tracing::error!("OIDC provider request to {} failed: {:?}", url, e);
+                AppError::OidcProviderError
+            })?
+            .json::<T>()
+            .await
+            .map_err(|e| {
+                tracing::error!("OIDC provider response from {} unreadable: {:?}", url, e);
+                AppError::OidcProviderError
+            })
+    }
+}
+
+/// The value of the `name` cookie in the request's `Cookie` headers.
+pub fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
@@ -0,0 +1,454 @@
+//! Token bucket rate limiting. Every client has a bucket per route group
+//! holding up to `burst` tokens and refilling at `per_minute`. A request
+//! takes a token and is turned away with 429 when the bucket is empty.
+
+use axum::http::{HeaderName, HeaderValue};
+use sqlx::PgPool;
+use std::{
+    collections::HashMap,
+    future::Future,
+    net::IpAddr,
+    pin::Pin,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use super::errors::AppError;
+use crate::{
+    AppState,
+    config::{RateLimitBackend, RateLimitConfig, RateLimitPolicy},
+};
+
+pub const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+pub const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+pub const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+// rejected requests sampled into the audit log, per client and overall
+const REJECTION_AUDIT_POLICY: RateLimitPolicy = RateLimitPolicy {
+    burst: 5,
+    per_minute: 5,
+};
+const REJECTION_AUDIT_TOTAL_POLICY: RateLimitPolicy = RateLimitPolicy {
+    burst: 60,
+    per_minute: 60,
+};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Routes sharing a policy and a bucket per client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteGroup {
+    Default,
+    // sign up and login, a stricter policy against credential stuffing
+    Auth,
+}
+
+impl RouteGroup {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Auth => "auth",
+        }
+    }
+}
+
+/// Who a bucket belongs to.
+#[derive(Debug, Clone, Copy)]
+pub enum Client {
+    User(i64),
+    Ip(IpAddr),
+}
+
+impl std::fmt::Display for Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::User(user_id) => write!(f, "user:{}", user_id),
+            Self::Ip(IpAddr::V4(ip)) => write!(f, "ip:{}", ip),
+            // a single host usually gets a whole /64, limit the prefix
+            Self::Ip(IpAddr::V6(ip)) => {
+                let prefix = u128::from(*ip) & !(u128::from(u64::MAX));
+                write!(f, "ip:{}/64", std::net::Ipv6Addr::from(prefix))
+            }
+        }
+    }
+}
+
+/// A store's answer for one request.
+#[derive(Debug, Clone, Copy)]
+pub struct Take {
+    pub allowed: bool,
+    // left in the bucket afterwards
+    pub tokens: f64,
+}
+
+/// Keeps the buckets. `take` has to be atomic: two requests racing for the
+/// last token must not both get it.
+pub trait RateLimitStore: Send + Sync + std::fmt::Debug {
+    fn take<'a>(
+        &'a self,
+        key: &'a str,
+        policy: RateLimitPolicy,
+    ) -> BoxFuture<'a, Result<Take, AppError>>;
+
+    /// Drops buckets untouched for `idle`, by then they are full again anyway.
+    fn prune(&self, idle: Duration) -> BoxFuture<'_, Result<u64, AppError>>;
+}
+
+/// Buckets in this process, each replica limits on its own.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl MemoryStore {
+    // `take` as of `now`, so the refill math can be tested without sleeping
+    fn take_at(&self, key: &str, policy: RateLimitPolicy, now: Instant) -> Take {
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: f64::from(policy.burst),
+            updated_at: now,
+        });
+
+        let tokens = (bucket.tokens
+            + now
+                .saturating_duration_since(bucket.updated_at)
+                .as_secs_f64()
+                * refill_rate(policy))
+        .min(f64::from(policy.burst));
+        let allowed = tokens >= 1.0;
+
+        bucket.tokens = if allowed { tokens - 1.0 } else { tokens };
+        bucket.updated_at = now;
+
+        Take {
+            allowed,
+            tokens: bucket.tokens,
+        }
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    fn take<'a>(
+        &'a self,
+        key: &'a str,
+        policy: RateLimitPolicy,
+    ) -> BoxFuture<'a, Result<Take, AppError>> {
+        Box::pin(async move { Ok(self.take_at(key, policy, Instant::now())) })
+    }
+
+    fn prune(&self, idle: Duration) -> BoxFuture<'_, Result<u64, AppError>> {
+        Box::pin(async move {
+            let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+            let before = buckets.len();
+            buckets.retain(|_, bucket| bucket.updated_at.elapsed() < idle);
+
+            Ok((before - buckets.len()) as u64)
+        })
+    }
+}
+
+/// Buckets in the `rate_limit_buckets` table, shared by every replica.
+#[derive(Debug)]
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl RateLimitStore for PostgresStore {
+    fn take<'a>(
+        &'a self,
+        key: &'a str,
+        policy: RateLimitPolicy,
+    ) -> BoxFuture<'a, Result<Take, AppError>> {
+        Box::pin(async move {
+            // refill and take in one statement, the row lock serializes racing requests
+            let (tokens, allowed) = sqlx::query_as::<_, (f64, bool)>(
+                r#"
+                INSERT INTO rate_limit_buckets AS bucket (key, tokens, allowed, updated_at)
+                VALUES ($1, $2 - 1, TRUE, NOW())
+                ON CONFLICT (key) DO UPDATE SET (tokens, allowed, updated_at) = (
+                    SELECT
+                        CASE WHEN refilled >= 1 THEN refilled - 1 ELSE refilled END,
+                        refilled >= 1,
+                        GREATEST(bucket.updated_at, NOW())
+                    FROM (
+                        SELECT LEAST(
+                            $2,
+                            bucket.tokens
+                                + GREATEST(EXTRACT(EPOCH FROM NOW() - bucket.updated_at)::FLOAT8, 0) * $3
+                        ) AS refilled
+                    ) AS refill
+                )
+                RETURNING tokens, allowed
+                "#,
+            )
+            .bind(key)
+            .bind(f64::from(policy.burst))
+            .bind(refill_rate(policy))
+            .fetch_one(&self.pool)
+            .await
+            .map_err(AppError::database("taking a rate limit token"))?;
+
+            Ok(Take { allowed, tokens })
+        })
+    }
+
+    fn prune(&self, idle: Duration) -> BoxFuture<'_, Result<u64, AppError>> {
+        Box::pin(async move {
+            let result = sqlx::query(
+                "DELETE FROM rate_limit_buckets WHERE updated_at < NOW() - make_interval(secs => $1)",
+            )
+            .bind(idle.as_secs_f64())
+            .execute(&self.pool)
+            .await
+            .map_err(AppError::database("pruning rate limit buckets"))?;
+
+            Ok(result.rows_affected())
+        })
+    }
+}
+
+/// The outcome for one request, reported in the `RateLimit-*` headers.
+#[derive(Debug, Clone, Copy)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    // seconds until the bucket is full again
+    pub reset: u64,
+    // seconds until the next token, for Retry-After on a 429
+    pub retry_after: u64,
+}
+
+impl Decision {
+    pub fn headers(&self) -> [(HeaderName, HeaderValue); 3] {
+        [
+            (RATELIMIT_LIMIT, HeaderValue::from(self.limit)),
+            (RATELIMIT_REMAINING, HeaderValue::from(self.remaining)),
+            (RATELIMIT_RESET, HeaderValue::from(self.reset)),
+        ]
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    store: Box<dyn RateLimitStore>,
+    // samples rejected requests for the audit log, kept even with limiting off
+    rejections: MemoryStore,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, pool: &PgPool) -> Self {
+        let store: Box<dyn RateLimitStore> = match config.backend {
+            RateLimitBackend::Memory => Box::new(MemoryStore::default()),
+            RateLimitBackend::Postgres => Box::new(PostgresStore { pool: pool.clone() }),
+        };
+
+        Self {
+            config,
+            store,
+            rejections: MemoryStore::default(),
+        }
+    }
+
+    fn policy(&self, group: RouteGroup) -> RateLimitPolicy {
+        match group {
+            RouteGroup::Default => self.config.default,
+            RouteGroup::Auth => self.config.auth,
+        }
+    }
+
+    /// Takes a token from `client`'s bucket for `group`, None when rate
+    /// limiting is turned off.
+    pub async fn check(
+        &self,
+        group: RouteGroup,
+        client: Client,
+    ) -> Result<Option<Decision>, AppError> {
+        if !self.config.enabled {
+            return Ok(None);
+        }
+
+        let policy = self.policy(group);
+        let key = format!("{}:{}", group.as_str(), client);
+        let take = self.store.take(&key, policy).await?;
+        let rate = refill_rate(policy);
+
+        Ok(Some(Decision {
+            allowed: take.allowed,
+            limit: policy.burst,
+            remaining: take.tokens.floor() as u32,
+            reset: ((f64::from(policy.burst) - take.tokens) / rate).ceil() as u64,
+            retry_after: ((1.0 - take.tokens) / rate).ceil().max(1.0) as u64,
+        }))
+    }
+
+    /// Takes a token like `check` for a limit applied by a service rather
+    /// than the middleware, turning an empty bucket into a 429. A failing
+    /// store doesn't hold the request up.
+    pub async fn enforce(&self, group: RouteGroup, client: Client) -> Result<(), AppError> {
+        match self.check(group, client).await {
+            Ok(Some(decision)) if !decision.allowed => Err(AppError::RateLimited {
+                retry_after: decision.retry_after,
+            }),
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::warn!("Rate limit check failed, not limiting: {}", e.chain());
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether a request rejected for bad credentials should be written to
+    /// the audit log. Only a sample per client and overall is, so bad
+    /// credentials can't be turned into a write load on the audit table.
+    pub async fn sample_rejection(&self, client: Client) -> bool {
+        let per_client = format!("rejected:{}", client);
+        let allowed = |take: Result<Take, AppError>| take.is_ok_and(|take| take.allowed);
+
+        allowed(
+            self.rejections
+                .take(&per_client, REJECTION_AUDIT_POLICY)
+                .await,
+        ) && allowed(
+            self.rejections
+                .take("rejected:*", REJECTION_AUDIT_TOTAL_POLICY)
+                .await,
+        )
+    }
+
+    /// Background job dropping idle buckets so the store doesn't grow with
+    /// every client ever seen.
+    pub async fn run_prune(app_state: AppState) {
+        let limiter = &app_state.rate_limiter;
+
+        // an empty bucket is full again after burst / rate
+        let idle = [
+            limiter.policy(RouteGroup::Default),
+            limiter.policy(RouteGroup::Auth),
+            REJECTION_AUDIT_POLICY,
+            REJECTION_AUDIT_TOTAL_POLICY,
+        ]
+        .into_iter()
+        .map(|policy| Duration::from_secs_f64(f64::from(policy.burst) / refill_rate(policy)))
+        .max()
+        .unwrap_or(PRUNE_INTERVAL);
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = app_state.shutdown.stopped() => break,
+            }
+
+            if limiter.config.enabled {
+                prune_store(limiter.store.as_ref(), idle).await;
+            }
+            prune_store(&limiter.rejections, idle).await;
+        }
+    }
+}
+
+async fn prune_store(store: &dyn RateLimitStore, idle: Duration) {
+    match store.prune(idle).await {
+        Ok(0) => {}
+        Ok(pruned) => tracing::debug!("Pruned {} idle rate limit buckets", pruned),
+        Err(e) => tracing::error!("Pruning rate limit buckets failed: {}", e.chain()),
+    }
+}
+
+// tokens per second
+fn refill_rate(policy: RateLimitPolicy) -> f64 {
+    f64::from(policy.per_minute) / 60.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn limiter() -> RateLimiter {
+        let config = test_support::config(&[]);
+        let pool = PgPool::connect_lazy(&config.database.url).expect("lazy pool");
+
+        RateLimiter::new(config.rate_limit_config, &pool)
+    }
+
+    #[tokio::test]
+    async fn rejections_are_sampled_per_client() {
+        let limiter = limiter();
+        let noisy = Client::Ip("192.0.2.1".parse().unwrap());
+        let quiet = Client::Ip("192.0.2.2".parse().unwrap());
+
+        for _ in 0..REJECTION_AUDIT_POLICY.burst {
+            assert!(limiter.sample_rejection(noisy).await);
+        }
+        assert!(!limiter.sample_rejection(noisy).await);
+        assert!(limiter.sample_rejection(quiet).await);
+    }
+
+    const ONE_PER_SECOND: RateLimitPolicy = RateLimitPolicy {
+        burst: 3,
+        per_minute: 60,
+    };
+
+    #[test]
+    fn burst_is_spent_then_refilled_at_the_rate() {
+        let store = MemoryStore::default();
+        let start = Instant::now();
+        let at = |secs: f64| start + Duration::from_secs_f64(secs);
+
+        let takes: Vec<_> = (0..3)
+            .map(|_| store.take_at("key", ONE_PER_SECOND, start))
+            .collect();
+        assert!(takes.iter().all(|take| take.allowed));
+        assert_eq!(takes[2].tokens, 0.0);
+        assert!(!store.take_at("key", ONE_PER_SECOND, start).allowed);
+
+        // half a token isn't enough, and the denied request doesn't cost any
+        let half = store.take_at("key", ONE_PER_SECOND, at(0.5));
+        assert!(!half.allowed);
+        assert_eq!(half.tokens, 0.5);
+
+        let refilled = store.take_at("key", ONE_PER_SECOND, at(1.0));
+        assert!(refilled.allowed);
+        assert_eq!(refilled.tokens, 0.0);
+    }
+
+    #[test]
+    fn refill_stops_at_the_burst() {
+        let store = MemoryStore::default();
+        let start = Instant::now();
+        let later = start + Duration::from_secs(3600);
+
+        assert!(store.take_at("key", ONE_PER_SECOND, start).allowed);
+        let takes: Vec<_> = (0..4)
+            .map(|_| store.take_at("key", ONE_PER_SECOND, later).allowed)
+            .collect();
+        assert_eq!(takes, [true, true, true, false]);
+    }
+
+    #[test]
+    fn buckets_are_kept_per_key() {
+        let store = MemoryStore::default();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            store.take_at("auth:ip:192.0.2.1", ONE_PER_SECOND, now);
+        }
+        assert!(
+            !store
+                .take_at("auth:ip:192.0.2.1", ONE_PER_SECOND, now)
+                .allowed
+        );
+        assert!(store.take_at("auth:user:1", ONE_PER_SECOND, now).allowed);
+    }
+}
@@ -0,0 +1,121 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{fmt, sync::OnceLock};
+
+// set once at startup from logging.log_pii and logging.pii_key
+static REDACTION: OnceLock<Redaction> = OnceLock::new();
+
+/// How [`Pii`] values are written to the log.
+pub enum Redaction {
+    /// In the clear, for local debugging.
+    Clear,
+    /// As a truncated HMAC-SHA256 under a server side key, so lines about
+    /// the same user can be correlated but the value can't be brute forced
+    /// back from a dictionary of usernames without the key.
+    Keyed(Hmac<Sha256>),
+}
+
+impl Redaction {
+    /// Without a configured key a random one is used, hashes then only
+    /// correlate within one process.
+    pub fn new(log_pii: bool, key: Option<&str>) -> Self {
+        if log_pii {
+            return Self::Clear;
+        }
+
+        let mac = match key {
+            Some(key) => Hmac::new_from_slice(key.as_bytes()),
+            None => Hmac::new_from_slice(&rand::random::<[u8; 32]>()),
+        };
+
+        Self::Keyed(mac.expect("HMAC accepts keys of any length"))
+    }
+
+    fn write(&self, value: &dyn fmt::Display, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mac = match self {
+            Self::Clear => return value.fmt(f),
+            Self::Keyed(mac) => mac,
+        };
+
+        let mut mac = mac.clone();
+        mac.update(value.to_string().as_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        write!(f, "pii:")?;
+        for byte in &digest[..8] {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn init(redaction: Redaction) {
+    if REDACTION.set(redaction).is_err() {
+        tracing::warn!("PII redaction already initialised, keeping the first setting");
+    }
+}
+
+/// Wraps a value that identifies a person, such as a username, for log
+/// output. Unless `LOG_PII` is on it prints as a keyed hash, see
+/// [`Redaction::Keyed`].
+pub struct Pii<T>(pub T);
+
+pub fn pii<T: fmt::Display>(value: T) -> Pii<T> {
+    Pii(value)
+}
+
+impl<T: fmt::Display> fmt::Display for Pii<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        REDACTION
+            .get_or_init(|| Redaction::new(false, None))
+            .write(&self.0, f)
+    }
+}
+
+impl<T: fmt::Display> fmt::Debug for Pii<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Shown<'a>(&'a Redaction, &'a str);
+
+    impl fmt::Display for Shown<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.write(&self.1, f)
+        }
+    }
+
+    fn show(redaction: &Redaction, value: &str) -> String {
+        Shown(redaction, value).to_string()
+    }
+
+    #[test]
+    fn log_pii_prints_the_value() {
+        let redaction = Redaction::new(true, Some("key"));
+
+        assert_eq!(show(&redaction, "alice"), "alice");
+    }
+
+    #[test]
+    fn redacted_values_depend_on_the_key() {
+        let redaction = Redaction::new(false, Some("key"));
+        let shown = show(&redaction, "alice");
+
+        assert!(
+            shown.starts_with("pii:") && shown.len() == 4 + 16,
+            "{shown}"
+        );
+        assert!(!shown.contains("alice"));
+        // stable for correlating log lines, distinct per user
+        assert_eq!(show(&redaction, "alice"), shown);
+        assert_ne!(show(&redaction, "bob"), shown);
+        // anyone without the key can't recompute it
+        assert_ne!(show(&Redaction::new(false, Some("other")), "alice"), shown);
+        assert_ne!(show(&Redaction::new(false, None), "alice"), shown);
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+use tokio::sync::watch;
+
+/// Shared shutdown state. Readiness flips to false as soon as shutdown
+/// starts, background workers wait on `stopped` to finish their loops.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    ready: Arc<AtomicBool>,
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            ready: Arc::new(AtomicBool::new(true)),
+            sender: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    /// False once shutdown has started, load balancers should stop routing here.
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    pub fn begin(&self) {
+        self.ready.store(false, Ordering::Relaxed);
+        self.sender.send_replace(true);
+    }
+
+    /// Resolves once `begin` has been called.
+    pub async fn stopped(&self) {
+        let mut receiver = self.sender.subscribe();
+        // the sender lives in self, so this can't fail while we wait
+        let _ = receiver.wait_for(|stopping| *stopping).await;
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Resolves on SIGINT (Ctrl-C) or SIGTERM.
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl-C: {:?}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {:?}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received SIGINT"),
+        _ = terminate => tracing::info!("Received SIGTERM"),
+    }
+}
@@ -0,0 +1,255 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::{
+    Context, KeyValue, global,
+    propagation::{Extractor, Injector},
+    trace::{Span as _, SpanKind, Tracer as _, TracerProvider as _},
+};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    Resource,
+    propagation::TraceContextPropagator,
+    trace::{SdkTracer, SdkTracerProvider},
+};
+use std::time::{Duration, SystemTime};
+use tracing::{Event, Subscriber, field::Field, level_filters::LevelFilter};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    EnvFilter, Layer, filter::Targets, layer::SubscriberExt, registry::LookupSpan,
+    util::SubscriberInitExt,
+};
+use uuid::Uuid;
+
+use super::redact;
+use crate::config::{LogFormat, LoggingConfig, TelemetryConfig};
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+// longer or non-printable incoming ids are replaced with a fresh one
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    /// Id of the request being handled, set by `middleware_trace`.
+    pub static REQUEST_ID: String;
+}
+
+/// Keeps the OTLP pipeline alive, `shutdown` flushes spans still buffered.
+#[derive(Debug)]
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    /// Installs the global subscriber: log lines to stdout and, when an OTLP
+    /// endpoint is configured, spans (including one per sqlx query) to the
+    /// collector over OTLP/HTTP.
+    pub fn init(
+        config: &TelemetryConfig,
+        logging: &LoggingConfig,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        redact::init(redact::Redaction::new(
+            logging.log_pii,
+            logging.pii_key.as_deref(),
+        ));
+
+        let provider = match &config.otlp_endpoint {
+            Some(endpoint) => {
+                let exporter = SpanExporter::builder()
+                    .with_http()
+                    .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+                    .build()?;
+
+                Some(
+                    SdkTracerProvider::builder()
+                        .with_batch_exporter(exporter)
+                        .with_resource(
+                            Resource::builder()
+                                .with_service_name(config.service_name.clone())
+                                .build(),
+                        )
+                        .build(),
+                )
+            }
+            None => None,
+        };
+
+        let tracer = provider
+            .as_ref()
+            .map(|provider| provider.tracer("tasks_backend"));
+
+        // spans follow the same filter as log lines
+        let otel_layer = tracer.clone().map(|tracer| {
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(EnvFilter::new(&logging.filter))
+        });
+        // sqlx only logs queries, these turn the logs into client spans
+        let query_layer = tracer.map(|tracer| {
+            QuerySpans { tracer }
+                .with_filter(Targets::new().with_target("sqlx::query", LevelFilter::DEBUG))
+        });
+
+        let fmt_layer = match logging.format {
+            LogFormat::Pretty => tracing_subscriber::fmt::layer().boxed(),
+            LogFormat::Json => tracing_subscriber::fmt::layer()
+                .json()
+                .flatten_event(true)
+                .with_current_span(true)
+                .with_span_list(false)
+                .boxed(),
+        };
+
+        tracing_subscriber::registry()
+            .with(fmt_layer.with_filter(EnvFilter::new(&logging.filter)))
+            .with(otel_layer)
+            .with(query_layer)
+            .try_init()?;
+
+        if let Some(endpoint) = &config.otlp_endpoint {
+            tracing::info!("Exporting traces to {}", endpoint);
+        }
+
+        Ok(Self { provider })
+    }
+
+    pub fn shutdown(self) {
+        if let Some(provider) = self.provider
+            && let Err(e) = provider.shutdown()
+        {
+            tracing::error!("Failed to flush traces: {:?}", e);
+        }
+    }
+}
+
+/// The incoming `X-Request-Id` when it is usable, a new UUID otherwise.
+pub fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.chars().all(|c| c.is_ascii_graphic())
+        })
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string())
+}
+
+/// The id of the request being handled, None outside a request.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Parent trace context from an incoming W3C `traceparent` header.
+pub fn extract_context(headers: &HeaderMap) -> Context {
+    global::get_text_map_propagator(|propagator| propagator.extract(&HeaderCarrier(headers)))
+}
+
+/// `traceparent` headers for an outgoing request, continuing the current trace.
+pub fn trace_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let context = tracing::Span::current().context();
+
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderCarrierMut(&mut headers))
+    });
+
+    headers
+}
+
+struct HeaderCarrier<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderCarrier<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+struct HeaderCarrierMut<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderCarrierMut<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Exports a client span for every `sqlx::query` log event, backdated by
+/// the query's elapsed time and parented to the span it ran under.
+struct QuerySpans {
+    tracer: SdkTracer,
+}
+
+impl<S> Layer<S> for QuerySpans
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+        let mut query = QueryFields::default();
+        event.record(&mut query);
+
+        let end = SystemTime::now();
+        let start = end - Duration::from_secs_f64(query.elapsed_secs.max(0.0));
+        let statement = match query.statement.trim() {
+            "" => query.summary.clone(),
+            statement => statement.to_string(),
+        };
+
+        let mut span = self
+            .tracer
+            .span_builder(query.summary)
+            .with_kind(SpanKind::Client)
+            .with_start_time(start)
+            .with_attributes([
+                KeyValue::new("db.system.name", "postgresql"),
+                KeyValue::new("db.query.text", statement),
+                KeyValue::new("db.response.returned_rows", query.rows_returned),
+                KeyValue::new("db.rows_affected", query.rows_affected),
+            ])
+            .start_with_context(&self.tracer, &tracing::Span::current().context());
+        span.end_with_timestamp(end);
+    }
+}
+
+#[derive(Default)]
+struct QueryFields {
+    summary: String,
+    statement: String,
+    rows_returned: i64,
+    rows_affected: i64,
+    elapsed_secs: f64,
+}
+
+impl tracing::field::Visit for QueryFields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "summary" => self.summary = value.to_string(),
+            "db.statement" => self.statement = value.to_string(),
+            _ => {}
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "rows_returned" => self.rows_returned = value as i64,
+            "rows_affected" => self.rows_affected = value as i64,
+            _ => {}
+        }
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "elapsed_secs" {
+            self.elapsed_secs = value;
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
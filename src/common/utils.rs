@@ -1,15 +1,47 @@
-use crate::common::errors::AppError;
+use crate::{common::errors::AppError, config::PasswordConfig};
 use argon2::{
-    Argon2,
+    Algorithm, Argon2, Params, Version,
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
 };
-pub struct PasswordUtils;
+use rand::{Rng, distr::Alphanumeric};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+pub const ACCESS_TOKEN_PREFIX: &str = "tbk_";
+const ACCESS_TOKEN_SECRET_LEN: usize = 40;
+const ACCESS_TOKEN_DISPLAY_LEN: usize = 12;
+
+/// Argon2id hashing with configurable cost.
+///
+/// Hashing is CPU and memory heavy, so every hash and verification runs on
+/// the blocking pool and at most `max_concurrent_hashes` run at once, a burst
+/// of logins queues up here instead of starving the async runtime.
+#[derive(Debug, Clone)]
+pub struct PasswordUtils {
+    argon2: Argon2<'static>,
+    permits: Arc<Semaphore>,
+}
 
 impl PasswordUtils {
+    pub fn new(config: &PasswordConfig) -> Result<Self, argon2::Error> {
+        let params = Params::new(
+            config.memory_cost_kib,
+            config.time_cost,
+            config.parallelism,
+            None,
+        )?;
+
+        Ok(Self {
+            argon2: Argon2::new(Algorithm::Argon2id, Version::V0x13, params),
+            permits: Arc::new(Semaphore::new(config.max_concurrent_hashes)),
+        })
+    }
+
     /// Hashes a plain-text password using Argon2.
     ///
-    /// This function generates a random salt and uses Argon2 with default parameters
-    /// to create a secure hash of the provided password.
+    /// This function generates a random salt and uses Argon2 with the
+    /// configured parameters to create a secure hash of the provided password.
     ///
     /// # Arguments
     ///
@@ -19,26 +51,115 @@ impl PasswordUtils {
     ///
     /// * `Ok(String)` - The hashed password as a string
     /// * `Err(AppError::PasswordHashingFailed)` - If hashing fails
-    pub fn hash_password(password: &str) -> Result<String, AppError> {
-        let salt = SaltString::generate(&mut OsRng);
-        let argon2 = Argon2::default();
+    pub async fn hash_password(&self, password: &str) -> Result<String, AppError> {
+        let argon2 = self.argon2.clone();
+        let password = password.to_string();
 
-        let password_hash = argon2
-            .hash_password(password.as_bytes(), &salt)
-            .map_err(|_| AppError::PasswordHashingFailed)?
-            .to_string();
+        self.run_blocking(move || {
+            let salt = SaltString::generate(&mut OsRng);
 
-        Ok(password_hash)
+            argon2
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+                .map_err(|_| AppError::PasswordHashingFailed)
+        })
+        .await?
     }
 
-    pub fn verfify_passowrd(password: &str, hash: &str) -> bool {
-        let password_hash = match PasswordHash::new(&hash) {
-            Ok(h) => h,
-            Err(_) => return false,
+    pub async fn verfify_passowrd(&self, password: &str, hash: &str) -> bool {
+        let argon2 = self.argon2.clone();
+        let password = password.to_string();
+        let hash = hash.to_string();
+
+        self.run_blocking(move || {
+            let password_hash = match PasswordHash::new(&hash) {
+                Ok(h) => h,
+                Err(_) => return false,
+            };
+
+            // the parameters stored in the hash are used, so old hashes keep verifying
+            argon2
+                .verify_password(password.as_bytes(), &password_hash)
+                .is_ok()
+        })
+        .await
+        .unwrap_or(false)
+    }
+
+    /// True when `hash` was made with another algorithm, version or cost
+    /// than the one configured, i.e. it should be replaced after a login.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let Ok(password_hash) = PasswordHash::new(hash) else {
+            return true;
+        };
+
+        let current = self.argon2.params();
+        let stored = match Params::try_from(&password_hash) {
+            Ok(params) => params,
+            Err(_) => return true,
         };
 
-        Argon2::default()
-            .verify_password(password.as_bytes(), &password_hash)
-            .is_ok()
+        password_hash.algorithm != Algorithm::Argon2id.ident()
+            || password_hash.version != Some(Version::V0x13.into())
+            || stored.m_cost() != current.m_cost()
+            || stored.t_cost() != current.t_cost()
+            || stored.p_cost() != current.p_cost()
+    }
+
+    async fn run_blocking<T, F>(&self, work: F) -> Result<T, AppError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let _permit = self.permits.acquire().await.map_err(|_| {
+            tracing::error!("Password hashing pool closed");
+            AppError::PasswordHashingFailed
+        })?;
+
+        tokio::task::spawn_blocking(work).await.map_err(|e| {
+            tracing::error!("Password hashing task failed: {:?}", e);
+            AppError::PasswordHashingFailed
+        })
+    }
+}
+
+pub struct TokenUtils;
+
+impl TokenUtils {
+    /// Generates a new personal access token, e.g. `tbk_Xk3...`.
+    ///
+    /// The prefix lets `middleware_auth` tell access tokens apart from JWTs.
+    pub fn generate_access_token() -> String {
+        format!(
+            "{}{}",
+            ACCESS_TOKEN_PREFIX,
+            Self::random_token(ACCESS_TOKEN_SECRET_LEN)
+        )
+    }
+
+    /// Random alphanumeric string from a CSPRNG, for state/nonce style values.
+    pub fn random_token(len: usize) -> String {
+        rand::rng()
+            .sample_iter(&Alphanumeric)
+            .take(len)
+            .map(char::from)
+            .collect()
+    }
+
+    /// Hashes an access token with SHA-256.
+    ///
+    /// Tokens are long random strings, so unlike passwords they don't need a
+    /// slow, salted hash and can be looked up by their digest directly.
+    pub fn hash_token(token: &str) -> String {
+        format!("{:x}", Sha256::digest(token.as_bytes()))
+    }
+
+    /// The leading part of a token that is safe to show in listings.
+    pub fn display_prefix(token: &str) -> String {
+        token.chars().take(ACCESS_TOKEN_DISPLAY_LEN).collect()
+    }
+
+    pub fn is_access_token(token: &str) -> bool {
+        token.starts_with(ACCESS_TOKEN_PREFIX)
     }
 }
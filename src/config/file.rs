@@ -0,0 +1,453 @@
+use super::{Config, ConfigError, LogFormat, RateLimitBackend};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+// read when neither --config nor CONFIG_FILE is given, if it exists
+const DEFAULT_PATH: &str = "config.toml";
+const REDACTED: &str = "REDACTED";
+
+// Shape of the TOML config file. Every field is optional, anything left out
+// comes from the environment or the built-in default, see `Config::load`.
+// `--print-config` serializes the effective config back into this shape.
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    pub database: DatabaseSection,
+    pub server: ServerSection,
+    pub limits: LimitsSection,
+    pub jwt: JwtSection,
+    pub password: PasswordSection,
+    pub mfa: MfaSection,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oidc: Option<OidcSection>,
+    pub audit: AuditSection,
+    pub account_deletion: AccountDeletionSection,
+    pub admin: AdminSection,
+    pub health: HealthSection,
+    pub api: ApiSection,
+    pub cors: CorsSection,
+    pub rate_limit: RateLimitSection,
+    pub telemetry: TelemetrySection,
+    pub logging: LoggingSection,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_connections: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_migrations: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shutdown_delay_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shutdown_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trusted_proxies: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub algorithm: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signing_key_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signing_key_path: Option<String>,
+    // kid = "path/to/public.pem"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verification_keys: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audience: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leeway: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_cost_kib: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_cost: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallelism: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_hashes: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MfaSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_token_expiration: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_challenge_attempts: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_failed_attempts: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lockout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OidcSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub issuer_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect_uri: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jit_provisioning: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditSection {
+    // 0 keeps events forever
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retention_days: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub purge_interval_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountDeletionSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grace_period_days: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub purge_interval_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_body_bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_in_flight: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub readiness_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub legacy_deprecated_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub legacy_sunset_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub docs_script_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub docs_script_integrity: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_origins: Option<Vec<String>>,
+    // allowed_origins per APP_ENV, used when allowed_origins is not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environments: Option<BTreeMap<String, Vec<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_methods: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_headers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exposed_headers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_credentials: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<RateLimitBackend>,
+    pub default: RateLimitPolicySection,
+    pub auth: RateLimitPolicySection,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitPolicySection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_minute: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetrySection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otlp_endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<LogFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_pii: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pii_key: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminSection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_ids: Option<Vec<i64>>,
+}
+
+impl FileConfig {
+    /// Reads the file at `path`, else `CONFIG_FILE`, else `./config.toml`.
+    /// No file at all is fine, everything can come from the environment.
+    pub fn read(path: Option<PathBuf>) -> Result<Self, ConfigError> {
+        let path = match path.or_else(|| std::env::var_os("CONFIG_FILE").map(PathBuf::from)) {
+            Some(path) => path,
+            None if Path::new(DEFAULT_PATH).exists() => PathBuf::from(DEFAULT_PATH),
+            None => return Ok(Self::default()),
+        };
+
+        tracing::info!("Reading config file {}", path.display());
+
+        let contents = std::fs::read_to_string(&path).map_err(|e| ConfigError::File {
+            path: path.clone(),
+            reason: e.to_string(),
+        })?;
+
+        Self::parse(&contents, path)
+    }
+
+    pub(super) fn parse(contents: &str, path: PathBuf) -> Result<Self, ConfigError> {
+        toml::from_str(contents).map_err(|e| ConfigError::File {
+            path,
+            reason: e.to_string().trim_end().to_string(),
+        })
+    }
+
+    pub(super) fn from_config(config: &Config) -> Self {
+        let jwt = &config.jwt_config;
+        let password = &config.password_config;
+        let cors = &config.cors_config;
+        let rate_limit = &config.rate_limit_config;
+
+        Self {
+            environment: Some(config.environment.clone()),
+            database: DatabaseSection {
+                url: Some(redact_url(&config.database.url)),
+                max_connections: Some(config.database.max_connections),
+                min_connections: Some(config.database.min_connections),
+                connection_timeout_secs: Some(config.database.connection_timeout.as_secs()),
+                idle_timeout_secs: Some(config.database.idle_timeout.as_secs()),
+                run_migrations: Some(config.database.run_migrations),
+            },
+            server: ServerSection {
+                host: Some(config.server.host.clone()),
+                port: Some(config.server.port),
+                shutdown_delay_secs: Some(config.server.shutdown_delay.as_secs()),
+                shutdown_timeout_secs: Some(config.server.shutdown_timeout.as_secs()),
+                metrics_host: Some(config.server.metrics_host.clone()),
+                metrics_port: Some(config.server.metrics_port),
+                trusted_proxies: Some(
+                    config
+                        .server
+                        .trusted_proxies
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect(),
+                ),
+            },
+            limits: LimitsSection {
+                max_body_bytes: Some(config.limits_config.max_body_bytes),
+                request_timeout_ms: Some(config.limits_config.request_timeout.as_millis() as u64),
+                max_in_flight: Some(config.limits_config.max_in_flight),
+            },
+            jwt: JwtSection {
+                secret: (!jwt.secret.is_empty()).then(|| REDACTED.to_string()),
+                expiration: Some(jwt.expiration),
+                algorithm: Some(format!("{:?}", jwt.algorithm)),
+                signing_key_id: Some(jwt.signing_key_id.clone()),
+                signing_key_path: jwt.signing_key_path.clone(),
+                verification_keys: (!jwt.verification_keys.is_empty())
+                    .then(|| jwt.verification_keys.iter().cloned().collect()),
+                issuer: Some(jwt.issuer.clone()),
+                audience: Some(jwt.audience.clone()),
+                leeway: Some(jwt.leeway),
+            },
+            password: PasswordSection {
+                memory_cost_kib: Some(password.memory_cost_kib),
+                time_cost: Some(password.time_cost),
+                parallelism: Some(password.parallelism),
+                max_concurrent_hashes: Some(password.max_concurrent_hashes),
+            },
+            mfa: MfaSection {
+                issuer: Some(config.mfa_config.issuer.clone()),
+                pending_token_expiration: Some(config.mfa_config.pending_token_expiration),
+                max_challenge_attempts: Some(config.mfa_config.max_challenge_attempts),
+                max_failed_attempts: Some(config.mfa_config.max_failed_attempts),
+                lockout_secs: Some(config.mfa_config.lockout.as_secs()),
+            },
+            oidc: config.oidc_config.as_ref().map(|oidc| OidcSection {
+                issuer_url: Some(oidc.issuer_url.clone()),
+                client_id: Some(oidc.client_id.clone()),
+                client_secret: oidc.client_secret.as_ref().map(|_| REDACTED.to_string()),
+                redirect_uri: Some(oidc.redirect_uri.clone()),
+                scopes: Some(oidc.scopes.clone()),
+                jit_provisioning: Some(oidc.jit_provisioning),
+            }),
+            audit: AuditSection {
+                retention_days: Some(config.audit_config.retention_days.unwrap_or(0)),
+                purge_interval_secs: Some(config.audit_config.purge_interval.as_secs()),
+            },
+            account_deletion: AccountDeletionSection {
+                grace_period_days: Some(config.account_deletion_config.grace_period_days),
+                purge_interval_secs: Some(config.account_deletion_config.purge_interval.as_secs()),
+            },
+            admin: AdminSection {
+                user_ids: Some(config.admin_config.user_ids.clone()),
+            },
+            health: HealthSection {
+                readiness_timeout_ms: Some(
+                    config.health_config.readiness_timeout.as_millis() as u64
+                ),
+            },
+            api: ApiSection {
+                legacy_deprecated_at: config.api_config.legacy_deprecated_at,
+                legacy_sunset_at: config.api_config.legacy_sunset_at,
+                docs_script_url: Some(config.api_config.docs_script_url.clone()),
+                docs_script_integrity: config.api_config.docs_script_integrity.clone(),
+            },
+            cors: CorsSection {
+                allowed_origins: Some(
+                    cors.allowed_origins
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect(),
+                ),
+                environments: None,
+                allowed_methods: Some(
+                    cors.allowed_methods
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect(),
+                ),
+                allowed_headers: Some(
+                    cors.allowed_headers
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect(),
+                ),
+                exposed_headers: Some(
+                    cors.exposed_headers
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect(),
+                ),
+                allow_credentials: Some(cors.allow_credentials),
+                max_age_secs: Some(cors.max_age.as_secs()),
+            },
+            rate_limit: RateLimitSection {
+                enabled: Some(rate_limit.enabled),
+                backend: Some(rate_limit.backend),
+                default: RateLimitPolicySection {
+                    burst: Some(rate_limit.default.burst),
+                    per_minute: Some(rate_limit.default.per_minute),
+                },
+                auth: RateLimitPolicySection {
+                    burst: Some(rate_limit.auth.burst),
+                    per_minute: Some(rate_limit.auth.per_minute),
+                },
+            },
+            telemetry: TelemetrySection {
+                otlp_endpoint: config.telemetry_config.otlp_endpoint.clone(),
+                service_name: Some(config.telemetry_config.service_name.clone()),
+            },
+            logging: LoggingSection {
+                format: Some(config.logging_config.format),
+                filter: Some(config.logging_config.filter.clone()),
+                log_pii: Some(config.logging_config.log_pii),
+                pii_key: config
+                    .logging_config
+                    .pii_key
+                    .as_ref()
+                    .map(|_| REDACTED.to_string()),
+            },
+        }
+    }
+}
+
+// keeps the host and database visible, only the password is hidden
+fn redact_url(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(mut parsed) if parsed.password().is_some() => {
+            let _ = parsed.set_password(Some(REDACTED));
+            parsed.to_string()
+        }
+        Ok(_) => url.to_string(),
+        Err(_) => REDACTED.to_string(),
+    }
+}
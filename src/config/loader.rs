@@ -0,0 +1,151 @@
+use super::ConfigError;
+use std::{collections::HashMap, str::FromStr};
+
+/// Resolves settings from the environment, then the config file, then the
+/// default, collecting every missing or invalid value instead of stopping at
+/// the first one.
+#[derive(Debug, Default)]
+pub(super) struct Loader {
+    pub errors: Vec<ConfigError>,
+    // stands in for the process environment when set, so tests don't touch it
+    vars: Option<HashMap<String, String>>,
+}
+
+impl Loader {
+    #[cfg(test)]
+    pub fn with_vars(vars: &[(&str, &str)]) -> Self {
+        Self {
+            errors: Vec::new(),
+            vars: Some(
+                vars.iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn var(&self, env: &str) -> Option<String> {
+        match &self.vars {
+            Some(vars) => vars.get(env).cloned(),
+            None => std::env::var(env).ok(),
+        }
+    }
+
+    /// `env` wins over `file`, which wins over `default`.
+    pub fn value<T>(
+        &mut self,
+        env: &'static str,
+        key: &'static str,
+        file: Option<T>,
+        default: T,
+    ) -> T
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.optional(env, key, file).unwrap_or(default)
+    }
+
+    pub fn optional<T>(
+        &mut self,
+        env: &'static str,
+        key: &'static str,
+        file: Option<T>,
+    ) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.optional_with(env, key, file, |value| {
+            value.parse::<T>().map_err(|e| e.to_string())
+        })
+    }
+
+    /// Like `optional`, with a custom parser for the environment value.
+    pub fn optional_with<T>(
+        &mut self,
+        env: &'static str,
+        key: &'static str,
+        file: Option<T>,
+        parse: impl FnOnce(&str) -> Result<T, String>,
+    ) -> Option<T> {
+        match self.var(env) {
+            Some(value) => match parse(value.trim()) {
+                Ok(value) => Some(value),
+                Err(reason) => {
+                    self.invalid(env, key, reason);
+                    None
+                }
+            },
+            None => file,
+        }
+    }
+
+    /// A comma separated list in the environment, an array in the file. Each
+    /// entry goes through `parse`, bad ones are reported and left out.
+    pub fn list<T>(
+        &mut self,
+        env: &'static str,
+        key: &'static str,
+        file: Option<Vec<String>>,
+        default: &[&str],
+        parse: impl Fn(&str) -> Result<T, String>,
+    ) -> Vec<T> {
+        let entries = self
+            .optional_with(env, key, file, |value| {
+                Ok(value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|entry| !entry.is_empty())
+                    .map(str::to_string)
+                    .collect())
+            })
+            .unwrap_or_else(|| default.iter().map(|entry| entry.to_string()).collect());
+
+        entries
+            .iter()
+            .filter_map(|entry| match parse(entry.trim()) {
+                Ok(value) => Some(value),
+                Err(reason) => {
+                    self.invalid(env, key, format!("{entry}: {reason}"));
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn required<T>(
+        &mut self,
+        env: &'static str,
+        key: &'static str,
+        file: Option<T>,
+    ) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        let value = self.optional(env, key, file);
+
+        // only report it missing when it is not already reported invalid
+        if value.is_none() && self.var(env).is_none() {
+            self.errors.push(ConfigError::Missing { env, key });
+        }
+
+        value
+    }
+
+    pub fn invalid(&mut self, env: &'static str, key: &'static str, reason: impl ToString) {
+        self.errors.push(ConfigError::Invalid {
+            env,
+            key,
+            reason: reason.to_string(),
+        });
+    }
+
+    /// Records `reason` against the setting unless `valid` holds.
+    pub fn check(&mut self, valid: bool, env: &'static str, key: &'static str, reason: &str) {
+        if !valid {
+            self.invalid(env, key, reason);
+        }
+    }
+}
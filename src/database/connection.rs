@@ -1,5 +1,4 @@
 use sqlx::{PgPool, postgres::PgPoolOptions};
-use std::time::Duration;
 
 use crate::config::DBConfig;
 
@@ -0,0 +1,157 @@
+use sqlx::{PgPool, migrate::MigrateError, migrate::Migrator};
+use thiserror::Error;
+
+// everything in migrations/, compiled into the binary
+static MIGRATOR: Migrator = sqlx::migrate!();
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error(
+        "database schema is at version {database} but this binary only knows up to {binary}, refusing to start"
+    )]
+    SchemaTooNew { database: i64, binary: i64 },
+
+    #[error("migration failed: {0}")]
+    Migrate(#[from] MigrateError),
+
+    #[error("database error checking the schema version: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Checks the schema version and, with `apply`, runs pending migrations.
+///
+/// Runs under a Postgres advisory lock so replicas starting together don't
+/// race, the first one migrates and the others wait and then find nothing
+/// left to do.
+#[tracing::instrument(name = "migrations", skip_all)]
+pub async fn prepare_schema(pool: &PgPool, apply: bool) -> Result<(), MigrationError> {
+    let mut conn = pool.acquire().await?;
+
+    sqlx::query("SELECT pg_advisory_lock(hashtext('tasks_backend_migrations'))")
+        .execute(&mut *conn)
+        .await?;
+
+    let result = check_and_apply(&mut conn, apply).await;
+
+    // the migration outcome matters more than the unlock, closing the
+    // connection ends its session and with it the lock
+    if let Err(e) = sqlx::query("SELECT pg_advisory_unlock(hashtext('tasks_backend_migrations'))")
+        .execute(&mut *conn)
+        .await
+    {
+        tracing::error!(
+            "Releasing the migration lock failed, closing the connection: {}",
+            e
+        );
+        if let Err(e) = conn.close().await {
+            tracing::error!("Closing the migration connection failed: {}", e);
+        }
+    }
+
+    result
+}
+
+async fn check_and_apply(conn: &mut sqlx::PgConnection, apply: bool) -> Result<(), MigrationError> {
+    let binary = expected_version();
+    let database = applied_version(conn).await?;
+
+    if database > binary {
+        return Err(MigrationError::SchemaTooNew { database, binary });
+    }
+
+    if apply {
+        tracing::info!("Applying migrations up to version {}", binary);
+        MIGRATOR.run_direct(conn).await?;
+        tracing::info!("Database schema is up to date");
+    } else if database < binary {
+        tracing::warn!(
+            "Database schema is at version {} but this binary expects {}, apply the pending migrations or enable database.run_migrations",
+            database,
+            binary
+        );
+    }
+
+    Ok(())
+}
+
+/// The newest migration compiled into this binary.
+pub fn expected_version() -> i64 {
+    MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0)
+}
+
+/// The newest migration applied to the database, 0 when nothing is.
+pub async fn applied_version(conn: &mut sqlx::PgConnection) -> Result<i64, sqlx::Error> {
+    let tracked =
+        sqlx::query_scalar::<_, bool>("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(&mut *conn)
+            .await?;
+
+    if !tracked {
+        return Ok(0);
+    }
+
+    sqlx::query_scalar::<_, Option<i64>>("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+        .fetch_one(&mut *conn)
+        .await
+        .map(|version| version.unwrap_or(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn lock_is_free(pool: &PgPool) -> bool {
+        let mut conn = pool.acquire().await.unwrap();
+        let free = sqlx::query_scalar::<_, bool>(
+            "SELECT pg_try_advisory_lock(hashtext('tasks_backend_migrations'))",
+        )
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+        conn.close().await.unwrap();
+
+        free
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn applies_everything_to_an_empty_database(pool: PgPool) {
+        prepare_schema(&pool, true).await.unwrap();
+
+        let mut conn = pool.acquire().await.unwrap();
+        assert_eq!(
+            applied_version(&mut conn).await.unwrap(),
+            expected_version()
+        );
+        drop(conn);
+        assert!(lock_is_free(&pool).await);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn replicas_starting_together_both_succeed(pool: PgPool) {
+        let (first, second) =
+            tokio::join!(prepare_schema(&pool, true), prepare_schema(&pool, true));
+
+        first.unwrap();
+        second.unwrap();
+        assert!(lock_is_free(&pool).await);
+    }
+
+    #[sqlx::test]
+    async fn newer_schema_is_refused_and_the_lock_released(pool: PgPool) {
+        sqlx::query(
+            "INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+             VALUES ($1, 'from a newer binary', TRUE, '\\x00', 0)",
+        )
+        .bind(expected_version() + 1)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let result = prepare_schema(&pool, true).await;
+        assert!(
+            matches!(result, Err(MigrationError::SchemaTooNew { .. })),
+            "{result:?}"
+        );
+        assert!(lock_is_free(&pool).await);
+    }
+}
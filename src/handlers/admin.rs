@@ -0,0 +1,202 @@
+use axum::{
+    Router,
+    extract::State,
+    routing::{get, post},
+};
+
+use crate::{
+    AppState,
+    common::{
+        api::{APIResponse, AppResponse, ErrorResponse},
+        extract::{Path, Query},
+        redact,
+    },
+    middleware::{Admin, RequireRole},
+    models::{admin, audit, session::ClientInfo},
+    services::{admin::AdminService, audit::AuditService},
+};
+
+pub fn admin_routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/audit", get(query_audit_log))
+        .route("/admin/users", get(list_users))
+        .route("/admin/users/{id}/disable", post(disable_user))
+        .route("/admin/users/{id}/enable", post(enable_user))
+        .route("/admin/users/{id}/reset_password", post(reset_password))
+        .route("/admin/stats", get(stats))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/audit",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(audit::AuditQuery),
+    responses(
+        (status = 200, description = "Matching audit events, newest first", body = APIResponse<Vec<audit::AuditEvent>>),
+        (status = 400, description = "Invalid filter", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Not an admin", body = ErrorResponse),
+        (status = 422, description = "Invalid query parameters", body = ErrorResponse),
+    )
+)]
+pub async fn query_audit_log(
+    State(app_state): State<AppState>,
+    RequireRole(admin, ..): RequireRole<Admin>,
+    Query(query): Query<audit::AuditQuery>,
+) -> AppResponse<Vec<audit::AuditEvent>> {
+    tracing::info!(
+        "querying audit log for admin: {}",
+        redact::pii(&admin.username)
+    );
+
+    match AuditService::query(&app_state, query).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/users",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(admin::ListUsersQuery),
+    responses(
+        (status = 200, description = "Matching users, newest first", body = APIResponse<Vec<admin::AdminUserSummary>>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Not an admin", body = ErrorResponse),
+        (status = 422, description = "Invalid query parameters", body = ErrorResponse),
+    )
+)]
+pub async fn list_users(
+    State(app_state): State<AppState>,
+    RequireRole(admin, ..): RequireRole<Admin>,
+    Query(query): Query<admin::ListUsersQuery>,
+) -> AppResponse<Vec<admin::AdminUserSummary>> {
+    tracing::info!("listing users for admin: {}", redact::pii(&admin.username));
+
+    match AdminService::list_users(&app_state, query).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/users/{id}/disable",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "User id")),
+    responses(
+        (status = 200, description = "User disabled and signed out", body = APIResponse<String>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Not an admin", body = ErrorResponse),
+        (status = 404, description = "No such user", body = ErrorResponse),
+        (status = 422, description = "Invalid id", body = ErrorResponse),
+    )
+)]
+pub async fn disable_user(
+    State(app_state): State<AppState>,
+    RequireRole(admin, ..): RequireRole<Admin>,
+    client: ClientInfo,
+    Path(user_id): Path<i64>,
+) -> AppResponse<String> {
+    tracing::info!(
+        "disabling user {} for admin: {}",
+        user_id,
+        redact::pii(&admin.username)
+    );
+
+    match AdminService::set_disabled(&app_state, admin.user_id, user_id, true, client).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/users/{id}/enable",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "User id")),
+    responses(
+        (status = 200, description = "User enabled", body = APIResponse<String>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Not an admin", body = ErrorResponse),
+        (status = 404, description = "No such user", body = ErrorResponse),
+        (status = 422, description = "Invalid id", body = ErrorResponse),
+    )
+)]
+pub async fn enable_user(
+    State(app_state): State<AppState>,
+    RequireRole(admin, ..): RequireRole<Admin>,
+    client: ClientInfo,
+    Path(user_id): Path<i64>,
+) -> AppResponse<String> {
+    tracing::info!(
+        "enabling user {} for admin: {}",
+        user_id,
+        redact::pii(&admin.username)
+    );
+
+    match AdminService::set_disabled(&app_state, admin.user_id, user_id, false, client).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/users/{id}/reset_password",
+    tag = "admin",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "User id")),
+    responses(
+        (status = 200, description = "A temporary password, shown once", body = APIResponse<admin::PasswordResetResponse>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Not an admin", body = ErrorResponse),
+        (status = 404, description = "No such user", body = ErrorResponse),
+        (status = 422, description = "Invalid id", body = ErrorResponse),
+    )
+)]
+pub async fn reset_password(
+    State(app_state): State<AppState>,
+    RequireRole(admin, ..): RequireRole<Admin>,
+    client: ClientInfo,
+    Path(user_id): Path<i64>,
+) -> AppResponse<admin::PasswordResetResponse> {
+    tracing::info!(
+        "resetting password of user {} for admin: {}",
+        user_id,
+        redact::pii(&admin.username)
+    );
+
+    match AdminService::reset_password(&app_state, admin.user_id, user_id, client).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/stats",
+    tag = "admin",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Counts across the whole system", body = APIResponse<admin::SystemStats>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Not an admin", body = ErrorResponse),
+    )
+)]
+pub async fn stats(
+    State(app_state): State<AppState>,
+    RequireRole(admin, ..): RequireRole<Admin>,
+) -> AppResponse<admin::SystemStats> {
+    tracing::info!("loading stats for admin: {}", redact::pii(&admin.username));
+
+    match AdminService::stats(&app_state).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
@@ -1,56 +1,148 @@
 use axum::{
-    Json, Router,
-    extract::{ConnectInfo, State},
-    http::HeaderMap,
+    Extension, Router,
+    extract::State,
     routing::{get, post},
 };
+use jsonwebtoken::jwk::JwkSet;
 
-use crate::common::api::AppResponse;
+use crate::common::api::{AppResponse, ErrorResponse};
 use crate::{
-    AppState, common::api::APIResponse, models::user, models::user::LoginResponse,
-    services::user::UserService,
+    AppState,
+    common::{api::APIResponse, errors::AppError, extract::Json, redact},
+    middleware::AuthenticatedUser,
+    models::mfa,
+    models::session::ClientInfo,
+    models::user,
+    models::user::{LoginResponse, LoginResult},
+    services::{session::SessionService, user::UserService},
 };
-use std::{net::SocketAddr, time::Instant};
-
 use tracing::instrument;
 
-use sqlx::PgPool;
 pub fn public_auth_routes() -> Router<AppState> {
     Router::new()
         .route("/sign_up", post(sign_up_handler))
         .route("/login", post(login_handler))
+        .route("/login/mfa", post(mfa_login_handler))
+}
+
+/// Served at the root so other services can find our public signing keys.
+pub fn jwks_routes() -> Router<AppState> {
+    Router::new().route("/.well-known/jwks.json", get(jwks_handler))
 }
 
 pub fn protected_auth_routes() -> Router<AppState> {
     Router::new().route("/logout", post(logout_handler))
 }
 
-#[instrument(skip(app_state, payload))]
+/// Creates an account, responds with the new user's id.
+#[utoipa::path(
+    post,
+    path = "/api/v1/sign_up",
+    tag = "auth",
+    request_body = user::SignupAndLoginPayload,
+    responses(
+        (status = 200, description = "Account created", body = APIResponse<i64>),
+        (status = 400, description = "Malformed JSON", body = ErrorResponse),
+        (status = 409, description = "Username taken", body = ErrorResponse),
+        (status = 422, description = "Missing or invalid fields, including the username rules", body = ErrorResponse),
+    )
+)]
+#[instrument(skip(app_state, client, payload))]
 pub async fn sign_up_handler(
     State(app_state): State<AppState>,
+    client: ClientInfo,
     Json(payload): Json<user::SignupAndLoginPayload>,
 ) -> AppResponse<i64> {
-    tracing::info!("Creating user: {}", payload.username);
+    tracing::info!("Creating user: {}", redact::pii(&payload.username));
 
-    let user_id = UserService::create_user(&app_state, payload).await?;
+    let user_id = UserService::create_user(&app_state, payload, client).await?;
 
     tracing::info!("Successfully updated user");
 
     Ok(APIResponse::success(user_id))
 }
 
+/// Checks the password. Users with two-factor authentication get an MFA
+/// challenge to finish at `/api/v1/login/mfa` instead of a token.
+#[utoipa::path(
+    post,
+    path = "/api/v1/login",
+    tag = "auth",
+    request_body = user::SignupAndLoginPayload,
+    responses(
+        (status = 200, description = "Signed in, or a second factor is required", body = APIResponse<LoginResult>),
+        (status = 401, description = "Wrong username or password", body = ErrorResponse),
+        (status = 403, description = "Account disabled", body = ErrorResponse),
+        (status = 422, description = "Missing or invalid fields", body = ErrorResponse),
+    )
+)]
 async fn login_handler(
     State(app_state): State<AppState>,
+    client: ClientInfo,
     Json(payload): Json<user::SignupAndLoginPayload>,
+) -> AppResponse<LoginResult> {
+    tracing::info!(
+        "Starting login process for {}",
+        redact::pii(&payload.username)
+    );
+
+    match UserService::login(&app_state, payload, client).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/login/mfa",
+    tag = "auth",
+    request_body = mfa::MfaLoginPayload,
+    responses(
+        (status = 200, description = "Signed in", body = APIResponse<LoginResponse>),
+        (status = 401, description = "Invalid or expired challenge or code", body = ErrorResponse),
+        (status = 422, description = "Missing or invalid fields", body = ErrorResponse),
+        (status = 429, description = "Too many invalid codes, locked out for a while", body = ErrorResponse),
+    )
+)]
+async fn mfa_login_handler(
+    State(app_state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<mfa::MfaLoginPayload>,
 ) -> AppResponse<LoginResponse> {
-    tracing::info!("Starting login process for {:?}", payload.username);
+    tracing::info!("Starting second factor login");
 
-    match UserService::login(&app_state, payload).await {
+    match UserService::complete_mfa_login(&app_state, payload, client).await {
         Ok(response) => Ok(APIResponse::success(response)),
         Err(err) => Err(err),
     }
 }
 
-async fn logout_handler() -> &'static str {
-    "Logout handler not yet created"
+/// Signs out the session the request was made with.
+#[utoipa::path(
+    post,
+    path = "/api/v1/logout",
+    tag = "auth",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Signed out", body = APIResponse<String>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't sign out", body = ErrorResponse),
+    )
+)]
+async fn logout_handler(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> AppResponse<String> {
+    tracing::info!("Logging out user: {}", redact::pii(&user.username));
+
+    let session_id = user.session_id.ok_or(AppError::Unauthorized)?;
+
+    match SessionService::revoke_session(&app_state, user.user_id, session_id).await {
+        Ok(_) => Ok(APIResponse::success("Logged out".to_string())),
+        Err(err) => Err(err),
+    }
+}
+
+async fn jwks_handler(State(app_state): State<AppState>) -> Json<JwkSet> {
+    Json(app_state.jwt_keys.jwks().clone())
 }
@@ -0,0 +1,32 @@
+use axum::{Json, Router, extract::State, http::StatusCode, routing::get};
+
+use crate::{
+    AppState,
+    models::health::{CheckStatus, HealthReport},
+    services::health::HealthService,
+};
+
+pub fn health_routes() -> Router<AppState> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+}
+
+pub async fn healthz() -> (StatusCode, Json<HealthReport>) {
+    respond(HealthService::liveness())
+}
+
+/// 503 as soon as shutdown starts or any check fails, so load balancers drain us.
+pub async fn readyz(State(app_state): State<AppState>) -> (StatusCode, Json<HealthReport>) {
+    respond(HealthService::readiness(&app_state).await)
+}
+
+// orchestrators read the status code, the body is for humans
+fn respond(report: HealthReport) -> (StatusCode, Json<HealthReport>) {
+    let status = match report.status {
+        CheckStatus::Pass => StatusCode::OK,
+        CheckStatus::Fail => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (status, Json(report))
+}
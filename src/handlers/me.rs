@@ -0,0 +1,215 @@
+use axum::{
+    Extension, Router,
+    extract::State,
+    http::header::CONTENT_DISPOSITION,
+    response::IntoResponse,
+    routing::{get, post, put},
+};
+
+use crate::{
+    AppState,
+    common::{
+        api::{APIResponse, AppResponse, ErrorResponse},
+        errors::AppError,
+        extract::Json,
+        redact,
+    },
+    middleware::AuthenticatedUser,
+    models::{account, session::ClientInfo, user},
+    services::{account::AccountService, user::UserService},
+};
+
+pub fn me_routes() -> Router<AppState> {
+    Router::new()
+        .route("/me", get(get_profile).patch(update_profile))
+        .route("/me/username", put(change_username))
+        .route(
+            "/me/deletion",
+            post(request_deletion).delete(cancel_deletion),
+        )
+        .route("/me/export", get(export_account))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/me",
+    tag = "me",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The user's profile", body = APIResponse<user::UserProfile>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't use this route", body = ErrorResponse),
+    )
+)]
+pub async fn get_profile(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> AppResponse<user::UserProfile> {
+    tracing::info!("getting profile for user: {}", redact::pii(&user.username));
+
+    match UserService::get_profile(&app_state, user.user_id).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v1/me",
+    tag = "me",
+    security(("bearer" = [])),
+    request_body = user::UpdateProfilePayload,
+    responses(
+        (status = 200, description = "The updated profile", body = APIResponse<user::UserProfile>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't use this route", body = ErrorResponse),
+        (status = 422, description = "Missing or invalid fields, e.g. an unknown time zone", body = ErrorResponse),
+    )
+)]
+pub async fn update_profile(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Json(payload): Json<user::UpdateProfilePayload>,
+) -> AppResponse<user::UserProfile> {
+    tracing::info!("updating profile for user: {}", redact::pii(&user.username));
+
+    match UserService::update_profile(&app_state, user.user_id, payload).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/api/v1/me/username",
+    tag = "me",
+    security(("bearer" = [])),
+    request_body = user::ChangeUsernamePayload,
+    responses(
+        (status = 200, description = "Renamed, every other session is signed out", body = APIResponse<user::LoginResponse>),
+        (status = 401, description = "Missing or invalid token, wrong password or two-factor code", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't use this route, or a recent sign in is required", body = ErrorResponse),
+        (status = 409, description = "Username taken", body = ErrorResponse),
+        (status = 422, description = "Missing or invalid fields, including the username rules", body = ErrorResponse),
+        (status = 429, description = "Too many invalid two-factor codes", body = ErrorResponse),
+    )
+)]
+pub async fn change_username(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+    client: ClientInfo,
+    Json(payload): Json<user::ChangeUsernamePayload>,
+) -> AppResponse<user::LoginResponse> {
+    tracing::info!(
+        "changing username for user: {}",
+        redact::pii(&user.username)
+    );
+
+    match UserService::change_username(&app_state, user.user_id, user.session_id, payload, client)
+        .await
+    {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+/// Schedules the account for deletion after a grace period, the password
+/// (and a two-factor code when enabled) must be confirmed first. Users
+/// without a password give their two-factor code, or sign in again first.
+#[utoipa::path(
+    post,
+    path = "/api/v1/me/deletion",
+    tag = "me",
+    security(("bearer" = [])),
+    request_body = account::ReauthenticatePayload,
+    responses(
+        (status = 200, description = "Deletion scheduled", body = APIResponse<account::AccountDeletionResponse>),
+        (status = 401, description = "Missing or invalid token, wrong password or two-factor code", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't use this route, or a recent sign in is required", body = ErrorResponse),
+        (status = 409, description = "Deletion already scheduled", body = ErrorResponse),
+        (status = 422, description = "Missing or invalid fields", body = ErrorResponse),
+        (status = 429, description = "Too many invalid two-factor codes", body = ErrorResponse),
+    )
+)]
+pub async fn request_deletion(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+    client: ClientInfo,
+    Json(payload): Json<account::ReauthenticatePayload>,
+) -> AppResponse<account::AccountDeletionResponse> {
+    tracing::info!(
+        "requesting deletion for user: {}",
+        redact::pii(&user.username)
+    );
+
+    match AccountService::request_deletion(
+        &app_state,
+        user.user_id,
+        user.session_id,
+        payload,
+        client,
+    )
+    .await
+    {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/me/deletion",
+    tag = "me",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Deletion cancelled", body = APIResponse<String>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't use this route", body = ErrorResponse),
+        (status = 404, description = "No deletion scheduled", body = ErrorResponse),
+    )
+)]
+pub async fn cancel_deletion(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+    client: ClientInfo,
+) -> AppResponse<String> {
+    tracing::info!(
+        "cancelling deletion for user: {}",
+        redact::pii(&user.username)
+    );
+
+    match AccountService::cancel_deletion(&app_state, user.user_id, client).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+/// Served as a download rather than wrapped in `APIResponse`, it is meant to
+/// be kept as a file.
+#[utoipa::path(
+    get,
+    path = "/api/v1/me/export",
+    tag = "me",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Everything held about the user, as a download", body = account::AccountExport),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't use this route", body = ErrorResponse),
+    )
+)]
+pub async fn export_account(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+    client: ClientInfo,
+) -> Result<impl IntoResponse, AppError> {
+    tracing::info!("exporting data for user: {}", redact::pii(&user.username));
+
+    let export = AccountService::export(&app_state, user.user_id, client).await?;
+    let disposition = format!(
+        "attachment; filename=\"account-{}-{}.json\"",
+        user.user_id,
+        export.exported_at.format("%Y%m%d%H%M%S")
+    );
+
+    Ok(([(CONTENT_DISPOSITION, disposition)], Json(export)))
+}
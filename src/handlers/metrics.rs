@@ -0,0 +1,19 @@
+use axum::{
+    Router, extract::State, http::header::CONTENT_TYPE, response::IntoResponse, routing::get,
+};
+
+use crate::{AppState, services::metrics::MetricsService};
+
+/// Served on its own listener (`METRICS_HOST`/`METRICS_PORT`), never on the
+/// public one.
+pub fn metrics_routes() -> Router<AppState> {
+    Router::new().route("/metrics", get(metrics))
+}
+
+/// Prometheus text exposition format.
+pub async fn metrics(State(app_state): State<AppState>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        MetricsService::scrape(&app_state),
+    )
+}
@@ -0,0 +1,76 @@
+use axum::{Extension, Router, extract::State, routing::post};
+
+use crate::{
+    AppState,
+    common::{
+        api::{APIResponse, AppResponse, ErrorResponse},
+        extract::Json,
+        redact,
+    },
+    middleware::AuthenticatedUser,
+    models::mfa,
+    services::mfa::MfaService,
+};
+
+pub fn mfa_routes() -> Router<AppState> {
+    Router::new()
+        .route("/mfa/totp/enroll", post(enroll_totp))
+        .route("/mfa/totp/confirm", post(confirm_totp))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/mfa/totp/enroll",
+    tag = "mfa",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "A new TOTP secret to confirm", body = APIResponse<mfa::TotpEnrollmentResponse>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't use this route", body = ErrorResponse),
+        (status = 409, description = "Two-factor authentication already enabled", body = ErrorResponse),
+    )
+)]
+pub async fn enroll_totp(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> AppResponse<mfa::TotpEnrollmentResponse> {
+    tracing::info!(
+        "starting TOTP enrollment for user: {}",
+        redact::pii(&user.username)
+    );
+
+    match MfaService::enroll_totp(&app_state, user.user_id).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/mfa/totp/confirm",
+    tag = "mfa",
+    security(("bearer" = [])),
+    request_body = mfa::MfaCodePayload,
+    responses(
+        (status = 200, description = "Enabled, the recovery codes are only shown once", body = APIResponse<mfa::RecoveryCodesResponse>),
+        (status = 400, description = "No enrollment started", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token, or wrong code", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't use this route", body = ErrorResponse),
+        (status = 422, description = "Missing or invalid fields", body = ErrorResponse),
+    )
+)]
+pub async fn confirm_totp(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Json(payload): Json<mfa::MfaCodePayload>,
+) -> AppResponse<mfa::RecoveryCodesResponse> {
+    tracing::info!(
+        "confirming TOTP enrollment for user: {}",
+        redact::pii(&user.username)
+    );
+
+    match MfaService::confirm_totp(&app_state, user.user_id, payload).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
@@ -1,2 +1,12 @@
+pub mod admin;
 pub mod auth;
+pub mod health;
+pub mod me;
+pub mod metrics;
+pub mod mfa;
+pub mod oidc;
+pub mod openapi;
+pub mod session;
 pub mod tasks;
+pub mod token;
+pub mod v1;
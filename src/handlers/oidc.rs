@@ -0,0 +1,115 @@
+use axum::{
+    Extension, Router,
+    extract::State,
+    http::{HeaderMap, header::SET_COOKIE},
+    response::{AppendHeaders, IntoResponse},
+    routing::get,
+};
+
+use crate::{
+    AppState,
+    common::{
+        api::{APIResponse, ErrorResponse},
+        errors::AppError,
+        extract::Query,
+        oidc::{self as oidc_client, STATE_COOKIE},
+        redact,
+    },
+    middleware::AuthenticatedUser,
+    models::{oidc, session::ClientInfo, user::LoginResult},
+    services::oidc::OidcService,
+};
+
+pub fn public_oidc_routes() -> Router<AppState> {
+    Router::new()
+        .route("/oidc/login", get(oidc_login_handler))
+        .route("/oidc/callback", get(oidc_callback_handler))
+}
+
+pub fn protected_oidc_routes() -> Router<AppState> {
+    Router::new().route("/oidc/link", get(oidc_link_handler))
+}
+
+/// Starts single sign-on with the configured identity provider. The state
+/// cookie it sets has to come back on the callback.
+#[utoipa::path(
+    get,
+    path = "/api/v1/oidc/login",
+    tag = "oidc",
+    responses(
+        (status = 200, description = "Where to send the browser", body = APIResponse<oidc::AuthorizationUrlResponse>),
+        (status = 404, description = "Single sign-on is not enabled", body = ErrorResponse),
+        (status = 502, description = "The identity provider could not be reached", body = ErrorResponse),
+    )
+)]
+async fn oidc_login_handler(
+    State(app_state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+    tracing::info!("Starting OIDC login");
+
+    let (response, cookie) = OidcService::start_login(&app_state, None).await?;
+
+    Ok(([(SET_COOKIE, cookie)], APIResponse::success(response)))
+}
+
+/// Starts linking an identity provider account to the signed in user.
+#[utoipa::path(
+    get,
+    path = "/api/v1/oidc/link",
+    tag = "oidc",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Where to send the browser", body = APIResponse<oidc::AuthorizationUrlResponse>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't use this route", body = ErrorResponse),
+        (status = 404, description = "Single sign-on is not enabled", body = ErrorResponse),
+        (status = 502, description = "The identity provider could not be reached", body = ErrorResponse),
+    )
+)]
+async fn oidc_link_handler(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> Result<impl IntoResponse, AppError> {
+    tracing::info!(
+        "Starting OIDC link for user: {}",
+        redact::pii(&user.username)
+    );
+
+    let (response, cookie) = OidcService::start_login(&app_state, Some(user.user_id)).await?;
+
+    Ok(([(SET_COOKIE, cookie)], APIResponse::success(response)))
+}
+
+/// Where the identity provider redirects back to. Users with two-factor
+/// authentication get an MFA challenge to finish at `/api/v1/login/mfa`.
+#[utoipa::path(
+    get,
+    path = "/api/v1/oidc/callback",
+    tag = "oidc",
+    params(oidc::CallbackParams),
+    responses(
+        (status = 200, description = "Signed in, or a second factor is required", body = APIResponse<LoginResult>),
+        (status = 400, description = "Sign-in attempt expired, already used or started in another browser", body = ErrorResponse),
+        (status = 401, description = "Single sign-on failed", body = ErrorResponse),
+        (status = 403, description = "No account is linked to this identity", body = ErrorResponse),
+        (status = 409, description = "Identity already linked to another account", body = ErrorResponse),
+        (status = 502, description = "The identity provider could not be reached", body = ErrorResponse),
+    )
+)]
+async fn oidc_callback_handler(
+    State(app_state): State<AppState>,
+    client: ClientInfo,
+    headers: HeaderMap,
+    Query(params): Query<oidc::CallbackParams>,
+) -> Result<impl IntoResponse, AppError> {
+    tracing::info!("Handling OIDC callback");
+
+    let state_cookie = oidc_client::cookie(&headers, STATE_COOKIE);
+    let response = OidcService::complete_login(&app_state, params, state_cookie, client).await?;
+    let clear_cookie = OidcService::clear_state_cookie(&app_state);
+
+    Ok((
+        AppendHeaders(clear_cookie.map(|cookie| (SET_COOKIE, cookie))),
+        APIResponse::success(response),
+    ))
+}
@@ -0,0 +1,163 @@
+use axum::{Json, Router, extract::State, response::Html, routing::get};
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+};
+
+use crate::{
+    AppState,
+    common::api::{APIError, ErrorResponse, FieldError, ProblemDetails},
+    handlers::{admin, auth, me, mfa, oidc, session, tasks, token},
+};
+
+/// The OpenAPI document for everything under `/api/v1`. A handler only shows
+/// up here once it has a `#[utoipa::path]` and is listed in `paths`.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "tasks_backend",
+        description = "Every response is an `APIResponse` envelope, errors an `ErrorResponse` \
+            with a stable `error.code`. Send `Accept: application/problem+json` to get errors \
+            as RFC 7807 `ProblemDetails` instead. The unversioned `/api` paths are deprecated \
+            aliases of `/api/v1`, answered with `Deprecation` and `Sunset` headers. Requests are \
+            rate limited per client IP and, once signed in, per user: `RateLimit-Limit`, \
+            `RateLimit-Remaining` and `RateLimit-Reset` report the budget, going over it is a 429 \
+            `rate_limited` error with `Retry-After`."
+    ),
+    paths(
+        auth::sign_up_handler,
+        auth::login_handler,
+        auth::mfa_login_handler,
+        auth::logout_handler,
+        tasks::get_user_tasks,
+        tasks::create_task,
+        tasks::update_task,
+        tasks::delete_task,
+        me::get_profile,
+        me::update_profile,
+        me::change_username,
+        me::request_deletion,
+        me::cancel_deletion,
+        me::export_account,
+        mfa::enroll_totp,
+        mfa::confirm_totp,
+        token::list_tokens,
+        token::create_token,
+        token::revoke_token,
+        session::list_sessions,
+        session::revoke_session,
+        oidc::oidc_login_handler,
+        oidc::oidc_link_handler,
+        oidc::oidc_callback_handler,
+        admin::query_audit_log,
+        admin::list_users,
+        admin::disable_user,
+        admin::enable_user,
+        admin::reset_password,
+        admin::stats,
+    ),
+    components(schemas(ErrorResponse, APIError, FieldError, ProblemDetails)),
+    modifiers(&BearerAuth),
+    tags(
+        (name = "auth", description = "Sign up, sign in and sign out"),
+        (name = "tasks", description = "The signed in user's tasks"),
+        (name = "me", description = "The signed in user's account"),
+        (name = "mfa", description = "Two-factor authentication"),
+        (name = "tokens", description = "Personal access tokens"),
+        (name = "sessions", description = "Signed in devices"),
+        (name = "oidc", description = "Single sign-on"),
+        (name = "admin", description = "Administration, admins only"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Login JWTs and personal access tokens are both sent as bearer tokens.
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT or personal access token")
+                    .build(),
+            ),
+        );
+    }
+}
+
+// Redoc comes from `api.docs_script_url`, the page only points it at the
+// document. The config loader keeps quotes and brackets out of both values.
+fn docs_page(script_url: &str, integrity: Option<&str>) -> String {
+    let integrity = integrity
+        .map(|hash| format!(r#" integrity="{hash}""#))
+        .unwrap_or_default();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title>tasks_backend API</title>
+    <meta charset="utf-8" />
+    <meta name="viewport" content="width=device-width, initial-scale=1" />
+  </head>
+  <body>
+    <redoc spec-url="openapi.json"></redoc>
+    <script src="{script_url}"{integrity} crossorigin="anonymous"></script>
+  </body>
+</html>
+"#
+    )
+}
+
+pub fn openapi_routes() -> Router<AppState> {
+    Router::new()
+        .route("/openapi.json", get(openapi_json))
+        .route("/docs", get(docs))
+}
+
+async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+async fn docs(State(app_state): State<AppState>) -> Html<String> {
+    let api_config = &app_state.api_config;
+
+    Html(docs_page(
+        &api_config.docs_script_url,
+        api_config.docs_script_integrity.as_deref(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ApiDoc;
+    use utoipa::OpenApi;
+
+    const SNAPSHOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+    /// `openapi.json` is what clients generate code from, so any change to a
+    /// handler signature or model has to show up in it. Regenerate with
+    /// `UPDATE_OPENAPI=1 cargo test` and commit the result.
+    #[test]
+    fn openapi_snapshot_is_up_to_date() {
+        let generated = ApiDoc::openapi()
+            .to_pretty_json()
+            .expect("OpenAPI document serializes")
+            + "\n";
+
+        if std::env::var_os("UPDATE_OPENAPI").is_some() {
+            std::fs::write(SNAPSHOT, &generated).expect("write openapi.json");
+            return;
+        }
+
+        let committed = std::fs::read_to_string(SNAPSHOT).unwrap_or_default();
+        assert!(
+            committed == generated,
+            "openapi.json is out of date, run `UPDATE_OPENAPI=1 cargo test` and commit it"
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use axum::{
+    Extension, Router,
+    extract::State,
+    routing::{delete, get},
+};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    common::{
+        api::{APIResponse, AppResponse, ErrorResponse},
+        extract::Path,
+        redact,
+    },
+    middleware::AuthenticatedUser,
+    models::session,
+    services::session::SessionService,
+};
+
+pub fn session_routes() -> Router<AppState> {
+    Router::new()
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/{id}", delete(revoke_session))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/sessions",
+    tag = "sessions",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The user's active sessions", body = APIResponse<Vec<session::Session>>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't use this route", body = ErrorResponse),
+    )
+)]
+pub async fn list_sessions(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> AppResponse<Vec<session::Session>> {
+    tracing::info!("listing sessions for user: {}", redact::pii(&user.username));
+
+    match SessionService::list_sessions(&app_state, user.user_id, user.session_id).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/sessions/{id}",
+    tag = "sessions",
+    security(("bearer" = [])),
+    params(("id" = Uuid, Path, description = "Session id")),
+    responses(
+        (status = 200, description = "Session signed out", body = APIResponse<String>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't use this route", body = ErrorResponse),
+        (status = 404, description = "No such session", body = ErrorResponse),
+        (status = 422, description = "Invalid id", body = ErrorResponse),
+    )
+)]
+pub async fn revoke_session(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(session_id): Path<Uuid>,
+) -> AppResponse<String> {
+    tracing::info!("revoking session for user: {}", redact::pii(&user.username));
+
+    match SessionService::revoke_session(&app_state, user.user_id, session_id).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
@@ -1,16 +1,20 @@
 use axum::{
-    Extension, Json, Router,
-    extract::{Path, Query, State},
-    routing::{delete, get, patch, post},
+    Extension, Router,
+    extract::State,
+    routing::{get, patch},
 };
 
 use uuid::Uuid;
 
 use crate::{
     AppState,
-    common::api::{APIResponse, AppResponse},
+    common::{
+        api::{APIResponse, AppResponse, ErrorResponse},
+        extract::{Json, Path},
+        redact,
+    },
     middleware::AuthenticatedUser,
-    models::task,
+    models::{task, token::TokenScope},
     services::task::TaskServices,
 };
 use uuid;
@@ -21,47 +25,115 @@ pub fn tasks_route() -> Router<AppState> {
         .route("/tasks/{id}", patch(update_task).delete(delete_task))
 }
 
+/// Every task of the signed in user, oldest update first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/tasks",
+    tag = "tasks",
+    security(("bearer" = ["tasks:read"])),
+    responses(
+        (status = 200, description = "The user's tasks", body = APIResponse<Vec<task::Task>>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the tasks:read scope", body = ErrorResponse),
+    )
+)]
 pub async fn get_user_tasks(
     State(app_state): State<AppState>,
     Extension(user): Extension<AuthenticatedUser>,
 ) -> AppResponse<Vec<task::Task>> {
-    tracing::info!("getting all tasks for user: {:?}", user.username);
+    tracing::info!(
+        "getting all tasks for user: {}",
+        redact::pii(&user.username)
+    );
+    user.require_scope(TokenScope::TasksRead)?;
 
     match TaskServices::get_tasks(&app_state, user.user_id).await {
         Ok(response) => Ok(APIResponse::success(response)),
         Err(err) => Err(err),
     }
 }
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/tasks",
+    tag = "tasks",
+    security(("bearer" = ["tasks:write"])),
+    request_body = task::CreateTaskPayload,
+    responses(
+        (status = 200, description = "The created task", body = APIResponse<task::TasksResponse>),
+        (status = 400, description = "Malformed JSON", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the tasks:write scope", body = ErrorResponse),
+        (status = 422, description = "Missing or invalid fields", body = ErrorResponse),
+    )
+)]
 pub async fn create_task(
     State(app_state): State<AppState>,
     Extension(user): Extension<AuthenticatedUser>,
     Json(task): Json<task::CreateTaskPayload>,
 ) -> AppResponse<task::TasksResponse> {
-    tracing::info!("creating task for user: {:?}", user.username);
+    tracing::info!("creating task for user: {}", redact::pii(&user.username));
+    user.require_scope(TokenScope::TasksWrite)?;
 
-    match TaskServices::create_task(&app_state, user.user_id, Json(task)).await {
+    match TaskServices::create_task(&app_state, user.user_id, task).await {
         Ok(response) => Ok(APIResponse::success(response)),
         Err(err) => Err(err),
     }
 }
+
+/// Fields left out of the body keep their current value.
+#[utoipa::path(
+    patch,
+    path = "/api/v1/tasks/{id}",
+    tag = "tasks",
+    security(("bearer" = ["tasks:write"])),
+    params(("id" = Uuid, Path, description = "Task id")),
+    request_body = task::UpdateTaskPayload,
+    responses(
+        (status = 200, description = "The updated task", body = APIResponse<task::TasksResponse>),
+        (status = 400, description = "Malformed JSON", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the tasks:write scope", body = ErrorResponse),
+        (status = 404, description = "No such task", body = ErrorResponse),
+        (status = 422, description = "Invalid id or fields", body = ErrorResponse),
+    )
+)]
 pub async fn update_task(
     State(app_state): State<AppState>,
     Extension(user): Extension<AuthenticatedUser>,
     Path(task_id): Path<Uuid>,
     Json(update_fields): Json<task::UpdateTaskPayload>,
 ) -> AppResponse<task::TasksResponse> {
-    tracing::info!("Edditing task {}", user.username);
+    tracing::info!("Edditing task {}", redact::pii(&user.username));
+    user.require_scope(TokenScope::TasksWrite)?;
 
     match TaskServices::update(&app_state, user.user_id, update_fields, task_id).await {
         Ok(response) => Ok(APIResponse::success(response)),
         Err(err) => Err(err),
     }
 }
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/tasks/{id}",
+    tag = "tasks",
+    security(("bearer" = ["tasks:write"])),
+    params(("id" = Uuid, Path, description = "Task id")),
+    responses(
+        (status = 200, description = "Task deleted", body = APIResponse<String>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Token lacks the tasks:write scope", body = ErrorResponse),
+        (status = 404, description = "No such task", body = ErrorResponse),
+        (status = 422, description = "Invalid id", body = ErrorResponse),
+    )
+)]
 pub async fn delete_task(
     State(app_state): State<AppState>,
     Extension(user): Extension<AuthenticatedUser>,
     Path(task_id): Path<Uuid>,
 ) -> AppResponse<String> {
+    user.require_scope(TokenScope::TasksWrite)?;
+
     match TaskServices::delete_task(&app_state, task_id).await {
         Ok(response) => Ok(APIResponse::success(response)),
         Err(err) => Err(err),
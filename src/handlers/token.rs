@@ -0,0 +1,109 @@
+use axum::{
+    Extension, Router,
+    extract::State,
+    routing::{delete, get},
+};
+
+use crate::{
+    AppState,
+    common::{
+        api::{APIResponse, AppResponse, ErrorResponse},
+        extract::{Json, Path},
+        redact,
+    },
+    middleware::AuthenticatedUser,
+    models::token,
+    services::token::AccessTokenService,
+};
+
+pub fn token_routes() -> Router<AppState> {
+    Router::new()
+        .route("/tokens", get(list_tokens).post(create_token))
+        .route("/tokens/{id}", delete(revoke_token))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/tokens",
+    tag = "tokens",
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The user's personal access tokens", body = APIResponse<Vec<token::AccessToken>>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't use this route", body = ErrorResponse),
+    )
+)]
+pub async fn list_tokens(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> AppResponse<Vec<token::AccessToken>> {
+    tracing::info!(
+        "listing access tokens for user: {}",
+        redact::pii(&user.username)
+    );
+
+    match AccessTokenService::list_tokens(&app_state, user.user_id).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/tokens",
+    tag = "tokens",
+    security(("bearer" = [])),
+    request_body = token::CreateAccessTokenPayload,
+    responses(
+        (status = 200, description = "The token, its plaintext is only shown once", body = APIResponse<token::CreatedAccessTokenResponse>),
+        (status = 400, description = "Missing name or scopes, or an expiry outside 1 to 3650 days", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't use this route", body = ErrorResponse),
+        (status = 422, description = "Missing or invalid fields", body = ErrorResponse),
+    )
+)]
+pub async fn create_token(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Json(payload): Json<token::CreateAccessTokenPayload>,
+) -> AppResponse<token::CreatedAccessTokenResponse> {
+    tracing::info!(
+        "creating access token for user: {}",
+        redact::pii(&user.username)
+    );
+
+    match AccessTokenService::create_token(&app_state, user.user_id, payload).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/tokens/{id}",
+    tag = "tokens",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "Token id")),
+    responses(
+        (status = 200, description = "Token revoked", body = APIResponse<String>),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Personal access tokens can't use this route", body = ErrorResponse),
+        (status = 404, description = "No such token", body = ErrorResponse),
+        (status = 422, description = "Invalid id", body = ErrorResponse),
+    )
+)]
+pub async fn revoke_token(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(token_id): Path<i64>,
+) -> AppResponse<String> {
+    tracing::info!(
+        "revoking access token for user: {}",
+        redact::pii(&user.username)
+    );
+
+    match AccessTokenService::revoke_token(&app_state, user.user_id, token_id).await {
+        Ok(response) => Ok(APIResponse::success(response)),
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,68 @@
+use axum::{Router, middleware as axum_middleware};
+
+use crate::{
+    AppState,
+    common::rate_limit::RouteGroup,
+    handlers::{
+        admin::admin_routes,
+        auth::{protected_auth_routes, public_auth_routes},
+        me::me_routes,
+        mfa::mfa_routes,
+        oidc::{protected_oidc_routes, public_oidc_routes},
+        session::session_routes,
+        tasks::tasks_route,
+        token::token_routes,
+    },
+    middleware::{
+        middleware_auth, middleware_rate_limit, middleware_require_admin,
+        middleware_require_session,
+    },
+};
+
+/// Everything served under `/api/v1`. A breaking change to a request or
+/// response model belongs in the next version's router, v1 clients keep
+/// getting the shapes they were built against.
+pub fn v1_routes(app_state: &AppState) -> Router<AppState> {
+    // account management needs a real login, access tokens only reach tasks.
+    // Rate limited per client IP before auth, so bad credentials count too,
+    // and per user inside it
+    let protected_api = Router::new()
+        .merge(protected_auth_routes())
+        .merge(me_routes())
+        .merge(mfa_routes())
+        .merge(token_routes())
+        .merge(session_routes())
+        .merge(protected_oidc_routes())
+        .merge(admin_routes().route_layer(axum_middleware::from_fn(middleware_require_admin)))
+        .route_layer(axum_middleware::from_fn(middleware_require_session))
+        .merge(tasks_route())
+        .route_layer(axum_middleware::from_fn_with_state(
+            (app_state.clone(), RouteGroup::Default),
+            middleware_rate_limit,
+        ))
+        .route_layer(axum_middleware::from_fn_with_state(
+            app_state.clone(),
+            middleware_auth,
+        ))
+        .route_layer(axum_middleware::from_fn_with_state(
+            (app_state.clone(), RouteGroup::Default),
+            middleware_rate_limit,
+        ));
+
+    // keyed by client IP, sign up and login get the stricter policy
+    let public_api = Router::new()
+        .merge(
+            public_auth_routes().route_layer(axum_middleware::from_fn_with_state(
+                (app_state.clone(), RouteGroup::Auth),
+                middleware_rate_limit,
+            )),
+        )
+        .merge(
+            public_oidc_routes().route_layer(axum_middleware::from_fn_with_state(
+                (app_state.clone(), RouteGroup::Default),
+                middleware_rate_limit,
+            )),
+        );
+
+    public_api.merge(protected_api)
+}
@@ -5,93 +5,293 @@ mod handlers;
 mod middleware;
 mod models;
 mod services;
+#[cfg(test)]
+mod test_support;
 
 use crate::{
-    config::{Config, JWTConfig},
-    database::connection::create_pool,
+    common::{
+        cors,
+        errors::AppError,
+        jwt::JwtKeys,
+        limits,
+        metrics::Metrics,
+        oidc::OidcClient,
+        rate_limit::RateLimiter,
+        shutdown::{self, Shutdown},
+        telemetry::Telemetry,
+        utils::PasswordUtils,
+    },
+    config::{
+        AccountDeletionConfig, AdminConfig, ApiConfig, AuditConfig, Config, HealthConfig,
+        JWTConfig, MfaConfig,
+    },
+    database::{connection::create_pool, migrations::prepare_schema},
     handlers::{
-        auth::{protected_auth_routes, public_auth_routes},
-        tasks::tasks_route,
+        auth::jwks_routes, health::health_routes, metrics::metrics_routes, openapi::openapi_routes,
+        v1::v1_routes,
     },
-    middleware::middleware_auth,
-};
-use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
-use tower_http::cors::CorsLayer;
-
-use axum::{
-    Router,
-    extract::Request,
-    http::{
-        HeaderValue, Method, StatusCode,
-        header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, ORIGIN},
+    middleware::{
+        middleware_client_ip, middleware_deprecated, middleware_metrics, middleware_trace,
+    },
+    services::{
+        account::AccountService, admin::AdminService, audit::AuditService, metrics::MetricsService,
     },
-    middleware as axum_middleware,
-    response::{IntoResponse, Response},
-    routing::{get, post},
 };
+use sqlx::{PgPool, types::ipnetwork::IpNetwork};
+
+use axum::{Router, middleware as axum_middleware, routing::get};
 
-use std::net::SocketAddr;
+use std::{future::IntoFuture, net::SocketAddr, path::PathBuf, sync::Arc};
 
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub pool: PgPool,
     pub jwt_config: JWTConfig,
+    pub jwt_keys: Arc<JwtKeys>,
+    pub password_utils: PasswordUtils,
+    pub mfa_config: MfaConfig,
+    pub oidc_client: Option<Arc<OidcClient>>,
+    pub audit_config: AuditConfig,
+    pub account_deletion_config: AccountDeletionConfig,
+    pub admin_config: AdminConfig,
+    pub health_config: HealthConfig,
+    pub api_config: ApiConfig,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub metrics: Arc<Metrics>,
+    pub shutdown: Shutdown,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt::init();
-
     dotenvy::dotenv().ok();
 
-    let config = Config::default().map_err(|e| format!("Config error: {:?}", e))?;
-    start_server(config).await?;
+    let args = Args::parse()?;
 
-    Ok(())
+    // the real subscriber is configured from the config, loading it logs through a plain one
+    let config = tracing::subscriber::with_default(tracing_subscriber::fmt().finish(), || {
+        Config::load(args.config_path).map_err(|errors| {
+            for error in &errors.0 {
+                tracing::error!("Config error: {}", error);
+            }
+            format!("Invalid configuration, {} error(s)", errors.0.len())
+        })
+    })?;
+
+    if args.print_config {
+        print!("{}", toml::to_string_pretty(&config.redacted())?);
+        return Ok(());
+    }
+
+    let telemetry = Telemetry::init(&config.telemetry_config, &config.logging_config)?;
+    let result = start_server(config).await;
+    telemetry.shutdown();
+
+    result
+}
+
+#[derive(Debug, Default)]
+struct Args {
+    // --config <path>, overrides CONFIG_FILE
+    config_path: Option<PathBuf>,
+
+    // --print-config, show the effective config with secrets redacted and exit
+    print_config: bool,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = Args::default();
+        let mut argv = std::env::args().skip(1);
+
+        while let Some(arg) = argv.next() {
+            match arg.as_str() {
+                "--config" => {
+                    let path = argv.next().ok_or("--config needs a path")?;
+                    args.config_path = Some(PathBuf::from(path));
+                }
+                "--print-config" => args.print_config = true,
+                other => match other.strip_prefix("--config=") {
+                    Some(path) => args.config_path = Some(PathBuf::from(path)),
+                    None => {
+                        return Err(format!(
+                            "Unknown argument {other}, usage: tasks_backend [--config <path>] [--print-config]"
+                        ));
+                    }
+                },
+            }
+        }
+
+        Ok(args)
+    }
 }
 
 pub async fn start_server(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    tracing::info!("Loading JWT keys");
+    let jwt_keys = JwtKeys::from_config(&config.jwt_config)?;
+
+    let password_utils = PasswordUtils::new(&config.password_config)
+        .map_err(|e| format!("Invalid Argon2 parameters: {}", e))?;
+
+    let oidc_client = match config.oidc_config {
+        Some(oidc_config) => {
+            tracing::info!("OIDC sign in enabled for {}", oidc_config.issuer_url);
+            Some(Arc::new(OidcClient::new(oidc_config)?))
+        }
+        None => None,
+    };
+
     tracing::info!("Initializing db connection");
+    let run_migrations = config.database.run_migrations;
     let pool = create_pool(config.database).await?;
+    prepare_schema(&pool, run_migrations).await?;
+
+    let rate_limiter = RateLimiter::new(config.rate_limit_config, &pool);
 
     let app_state = AppState {
         pool,
         jwt_config: config.jwt_config,
+        jwt_keys: Arc::new(jwt_keys),
+        password_utils,
+        mfa_config: config.mfa_config,
+        oidc_client,
+        audit_config: config.audit_config,
+        account_deletion_config: config.account_deletion_config,
+        admin_config: config.admin_config,
+        health_config: config.health_config,
+        api_config: config.api_config,
+        rate_limiter: Arc::new(rate_limiter),
+        metrics: Arc::new(Metrics::new()?),
+        shutdown: Shutdown::new(),
     };
 
-    let cors_layer = CorsLayer::new()
-        .allow_origin("*".parse::<HeaderValue>().unwrap())
-        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
-        .allow_headers([AUTHORIZATION, CONTENT_TYPE, ACCEPT, ORIGIN]);
+    AdminService::bootstrap_admins(&app_state).await?;
 
-    tracing::info!("Setting up routes");
+    let workers = [
+        tokio::spawn(AuditService::run_retention(app_state.clone())),
+        tokio::spawn(AccountService::run_purge(app_state.clone())),
+        tokio::spawn(RateLimiter::run_prune(app_state.clone())),
+        tokio::spawn(MetricsService::run_refresh(app_state.clone())),
+    ];
 
-    let protected_api = Router::new()
-        .merge(protected_auth_routes())
-        .merge(tasks_route())
-        .route_layer(axum_middleware::from_fn_with_state(
-            app_state.clone(),
-            middleware_auth,
-        ));
+    let cors_layer = cors::layer(&config.cors_config);
 
-    let public_api = Router::new().merge(public_auth_routes());
+    tracing::info!("Setting up routes");
+
+    // health checks stay outside the limits, they must answer under load
+    let api = limits::apply(
+        Router::new()
+            .nest("/api/v1", v1_routes(&app_state))
+            // the unversioned paths predate /api/v1, served until the sunset date
+            .nest(
+                "/api",
+                v1_routes(&app_state)
+                    .layer(axum_middleware::from_fn_with_state(
+                        app_state.clone(),
+                        middleware_deprecated,
+                    ))
+                    .merge(openapi_routes()),
+            ),
+        &config.limits_config,
+    );
 
     let app = Router::new()
         .route("/", get(root))
-        .nest("/api", public_api.merge(protected_api))
+        .merge(health_routes())
+        .merge(jwks_routes())
+        .merge(api)
+        .fallback(not_found)
+        .method_not_allowed_fallback(method_not_allowed)
+        .route_layer(axum_middleware::from_fn_with_state(
+            app_state.clone(),
+            middleware_metrics,
+        ))
         .layer(cors_layer)
-        .with_state(app_state) // new way of sharing state
+        .layer(axum_middleware::from_fn_with_state(
+            Arc::<[IpNetwork]>::from(config.server.trusted_proxies.clone()),
+            middleware_client_ip,
+        ))
+        .layer(axum_middleware::from_fn(middleware_trace))
+        .with_state(app_state.clone()) // new way of sharing state
         .into_make_service_with_connect_info::<SocketAddr>();
 
     let server_address = format!("{}:{}", config.server.host, config.server.port);
     let listener = tokio::net::TcpListener::bind(&server_address).await?;
 
+    // scrapes keep working while we drain, the listener goes away with the process
+    let metrics_address = format!(
+        "{}:{}",
+        config.server.metrics_host, config.server.metrics_port
+    );
+    let metrics_listener = tokio::net::TcpListener::bind(&metrics_address).await?;
+    let metrics_app = metrics_routes().with_state(app_state.clone());
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(metrics_listener, metrics_app).await {
+            tracing::error!("Metrics listener failed: {:?}", e);
+        }
+    });
+
+    let shutdown = app_state.shutdown.clone();
+    let shutdown_delay = config.server.shutdown_delay;
+    let server = axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            shutdown::signal().await;
+
+            // readiness goes first so load balancers stop sending us traffic
+            shutdown.begin();
+            tracing::info!("Shutting down, readiness is now failing");
+            tokio::time::sleep(shutdown_delay).await;
+
+            tracing::info!("No longer accepting connections, draining in-flight requests");
+        })
+        .into_future();
+
+    // the drain timeout starts when shutdown does
+    let drain_deadline = async {
+        app_state.shutdown.stopped().await;
+        tokio::time::sleep(shutdown_delay + config.server.shutdown_timeout).await;
+    };
+
     tracing::info!("Server has started");
-    axum::serve(listener, app).await?;
+    tokio::select! {
+        result = server => result?,
+        _ = drain_deadline => tracing::warn!(
+            "Requests still running after {:?}, dropping them",
+            config.server.shutdown_timeout
+        ),
+    }
+
+    // workers stop at their next wake up, one stuck mid-query must not hold up the exit
+    let workers_stopped = async {
+        for worker in workers {
+            if let Err(e) = worker.await {
+                tracing::error!("Background worker failed: {:?}", e);
+            }
+        }
+    };
+    if tokio::time::timeout(config.server.shutdown_timeout, workers_stopped)
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            "Background workers still running after {:?}, leaving them behind",
+            config.server.shutdown_timeout
+        );
+    }
+
+    app_state.pool.close().await;
+    tracing::info!("Shutdown complete");
+
     Ok(())
 }
 
 async fn root() -> &'static str {
     "Hello, World!"
 }
+
+async fn not_found() -> AppError {
+    AppError::NotFound
+}
+
+async fn method_not_allowed() -> AppError {
+    AppError::MethodNotAllowed
+}
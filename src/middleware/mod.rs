@@ -1,26 +1,143 @@
 use axum::{
-    extract::{Request, State},
-    http::header::AUTHORIZATION,
+    extract::{ConnectInfo, FromRequestParts, MatchedPath, OriginalUri, Request, State},
+    http::{
+        Extensions, HeaderName, HeaderValue,
+        header::{AUTHORIZATION, LINK, USER_AGENT},
+        request::Parts,
+    },
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
+};
+use std::{
+    convert::Infallible,
+    marker::PhantomData,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Instant,
 };
 
 use serde::{Deserialize, Serialize};
+use sqlx::types::ipnetwork::IpNetwork;
+use uuid::Uuid;
 
 use crate::AppState;
-use crate::common::{errors::AppError, jwt};
+use crate::common::{
+    api,
+    client_ip::{self, ClientIp},
+    errors::{AppError, ErrorVariant},
+    jwt,
+    limits::RequestLimits,
+    rate_limit::{Client, RouteGroup},
+    redact, telemetry,
+    utils::TokenUtils,
+};
+use crate::models::{
+    audit::{AuditEventType, NewAuditEvent},
+    session::ClientInfo,
+    token::TokenScope,
+    user::Role,
+};
+use crate::services::{audit::AuditService, session::SessionService, token::AccessTokenService};
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-use sqlx::PgPool;
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+const SUNSET: HeaderName = HeaderName::from_static("sunset");
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AuthenticatedUser {
     pub user_id: i64,
     pub username: String,
+    pub role: Role,
+    // None for a login session (JWT), Some for a personal access token
+    pub scopes: Option<Vec<TokenScope>>,
+    // the login session behind a JWT, None for a personal access token
+    pub session_id: Option<Uuid>,
 }
 
 impl AuthenticatedUser {
-    pub fn new(username: String, user_id: i64) -> Self {
-        Self { user_id, username }
+    pub fn new(username: String, user_id: i64, role: Role, session_id: Uuid) -> Self {
+        Self {
+            user_id,
+            username,
+            role,
+            scopes: None,
+            session_id: Some(session_id),
+        }
+    }
+
+    pub fn with_scopes(
+        username: String,
+        user_id: i64,
+        role: Role,
+        scopes: Vec<TokenScope>,
+    ) -> Self {
+        Self {
+            user_id,
+            username,
+            role,
+            scopes: Some(scopes),
+            session_id: None,
+        }
+    }
+
+    pub fn is_access_token(&self) -> bool {
+        self.scopes.is_some()
+    }
+
+    /// Login sessions can do everything, access tokens only what they were granted.
+    pub fn require_scope(&self, scope: TokenScope) -> Result<(), AppError> {
+        match &self.scopes {
+            Some(scopes) if !scopes.contains(&scope) => {
+                tracing::warn!(
+                    "Access token for user {} lacks scope {}",
+                    self.user_id,
+                    scope
+                );
+                Err(AppError::Unauthorized)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A role a route can demand through [`RequireRole`].
+pub trait RoleRequirement {
+    const ROLE: Role;
+}
+
+pub struct Admin;
+
+impl RoleRequirement for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+/// Extracts the [`AuthenticatedUser`] only if they have role `R`, rejecting
+/// everyone else with `AppError::Unauthorized`. Access tokens never pass, a
+/// role guards account wide powers that scopes were not designed for.
+pub struct RequireRole<R: RoleRequirement>(pub AuthenticatedUser, pub PhantomData<R>);
+
+impl<S: Send + Sync, R: RoleRequirement> FromRequestParts<S> for RequireRole<R> {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let user = parts
+            .extensions
+            .get::<AuthenticatedUser>()
+            .cloned()
+            .ok_or(AppError::MissingToken)?;
+
+        if user.role != R::ROLE || user.is_access_token() {
+            tracing::warn!(
+                "User {} lacks role {:?} for {}",
+                user.user_id,
+                R::ROLE,
+                parts.uri.path()
+            );
+            return Err(AppError::Unauthorized);
+        }
+
+        Ok(Self(user, PhantomData))
     }
 }
 
@@ -30,6 +147,70 @@ pub async fn middleware_auth(
     next: Next,
 ) -> Result<Response, AppError> {
     let (mut parts, body) = req.into_parts();
+
+    let authenticated_user = match authenticate(&app_state, &parts).await {
+        Ok(user) => user,
+        Err(e) => {
+            audit_rejection(&app_state, &parts, &e).await;
+            return Err(e);
+        }
+    };
+
+    tracing::info!(
+        "Auth completed for user {} ({})",
+        authenticated_user.user_id,
+        redact::pii(&authenticated_user.username)
+    );
+
+    // inject current user into request extensions
+    parts.extensions.insert(authenticated_user);
+
+    let req = Request::from_parts(parts, body);
+
+    Ok(next.run(req).await)
+}
+
+/// Audits a rejected token in the background. Missing and expired tokens
+/// are routine and skipped, the rest may point at a forged or stolen token
+/// and is sampled per client so a flood of them stays cheap.
+async fn audit_rejection(app_state: &AppState, parts: &Parts, error: &AppError) {
+    let suspicious = matches!(
+        error,
+        AppError::InvalidToken
+            | AppError::TokenNotYetValid
+            | AppError::InvalidTokenIssuer
+            | AppError::InvalidTokenAudience
+            | AppError::SessionRevoked
+            | AppError::AccountDisabled
+    );
+    if !suspicious {
+        return;
+    }
+
+    let client = client_info(parts);
+    let sampled = app_state
+        .rate_limiter
+        .sample_rejection(Client::Ip(
+            client
+                .ip_address
+                .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        ))
+        .await;
+    if !sampled {
+        tracing::debug!("Not auditing rejected token, sample taken for this client");
+        return;
+    }
+
+    let event = NewAuditEvent::failure(
+        AuditEventType::TokenRejected,
+        &client,
+        format!("{} on {}", error, parts.uri.path()),
+    );
+    let app_state = app_state.clone();
+    tokio::spawn(async move { AuditService::record(&app_state, event).await });
+}
+
+async fn authenticate(app_state: &AppState, parts: &Parts) -> Result<AuthenticatedUser, AppError> {
     let token = parts
         .headers
         .get(AUTHORIZATION)
@@ -37,35 +218,320 @@ pub async fn middleware_auth(
         .and_then(|s| s.strip_prefix("Bearer "))
         .ok_or_else(|| {
             tracing::error!("Missing/invalid auth header for {}", parts.uri.path());
-            AppError::InvalidToken
+            AppError::MissingToken
         })?;
 
-    // Verify jwt and extract claims
-    let claims = jwt::verify_token(token, &app_state.jwt_config).map_err(|e| {
-        tracing::error!("JWT verification failed for {}: {:?}", parts.uri.path(), e);
+    let authenticated_user = if TokenUtils::is_access_token(token) {
+        let (owner, scopes) = AccessTokenService::authenticate(app_state, token)
+            .await
+            .inspect_err(|e| {
+                tracing::error!(
+                    "Access token verification failed for {}: {:?}",
+                    parts.uri.path(),
+                    e
+                );
+            })?;
 
-        AppError::InvalidToken
-    })?;
+        tracing::debug!(
+            "Access token verified for {} ({}) accessing {}",
+            owner.user_id,
+            redact::pii(&owner.username),
+            parts.uri.path()
+        );
 
-    tracing::debug!(
-        "JWT verified for {} ({}) accessing {}",
-        claims.user_id,
-        claims.username,
-        parts.uri.path()
-    );
+        AuthenticatedUser::with_scopes(owner.username, owner.user_id, owner.role, scopes)
+    } else {
+        // Verify jwt and extract claims
+        let claims = jwt::verify_token(token, &app_state.jwt_keys).map_err(|e| {
+            let reason = jwt::rejection_reason(&e);
+            tracing::error!(
+                "JWT verification failed for {}: {} ({:?})",
+                parts.uri.path(),
+                reason,
+                e
+            );
+
+            reason
+        })?;
 
-    // inject current user into db pool and request extensions
-    let authenticated_user = AuthenticatedUser::new(claims.username.clone(), claims.user_id);
+        tracing::debug!(
+            "JWT verified for {} ({}) accessing {}",
+            claims.user_id,
+            redact::pii(&claims.username),
+            parts.uri.path()
+        );
 
-    parts.extensions.insert(authenticated_user);
+        SessionService::ensure_active(
+            app_state,
+            claims.user_id,
+            &claims.username,
+            claims.role,
+            claims.sid,
+        )
+        .await
+        .inspect_err(|e| {
+            tracing::warn!(
+                "Session {} rejected for {}: {:?}",
+                claims.sid,
+                parts.uri.path(),
+                e
+            );
+        })?;
 
-    let req = Request::from_parts(parts, body);
+        AuthenticatedUser::new(claims.username, claims.user_id, claims.role, claims.sid)
+    };
 
-    tracing::info!(
-        "Auth completed for user {} ({})",
-        claims.user_id,
-        claims.username
+    Ok(authenticated_user)
+}
+
+/// Opens the span every log line of a request belongs to, continuing the
+/// caller's trace from `traceparent`, and tags the request with an
+/// `X-Request-Id` that is echoed back and included in error bodies. Also
+/// records whether errors should be rendered as `application/problem+json`.
+pub async fn middleware_trace(req: Request, next: Next) -> Response {
+    let request_id = telemetry::request_id(req.headers());
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        http.request.method = %req.method(),
+        url.path = %req.uri().path(),
+        http.route = tracing::field::Empty,
+        http.response.status_code = tracing::field::Empty,
+        otel.name = tracing::field::Empty,
+        otel.kind = "server",
     );
+    if let Err(e) = span.set_parent(telemetry::extract_context(req.headers())) {
+        tracing::debug!("Failed to continue incoming trace: {:?}", e);
+    }
+
+    let problem_json = api::accepts_problem_json(req.headers());
+    let mut response = telemetry::REQUEST_ID
+        .scope(
+            request_id.clone(),
+            api::WANTS_PROBLEM_JSON.scope(problem_json, next.run(req)),
+        )
+        .instrument(span.clone())
+        .await;
+
+    span.record("http.response.status_code", response.status().as_u16());
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response
+            .headers_mut()
+            .insert(telemetry::X_REQUEST_ID, value);
+    }
+
+    response
+}
+
+/// Records request count and latency per matched route, and error responses
+/// per `AppError` variant. Added with `route_layer` so `MatchedPath` is set
+/// and unknown paths don't each get their own label.
+pub async fn middleware_metrics(
+    State(app_state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+
+    tracing::Span::current().record("http.route", route.as_str());
+    tracing::Span::current().record("otel.name", format!("{method} {route}"));
+
+    let response = next.run(req).await;
+
+    let metrics = &app_state.metrics;
+    metrics
+        .http_requests
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+    metrics
+        .http_request_duration
+        .with_label_values(&[&method, &route])
+        .observe(started.elapsed().as_secs_f64());
+
+    if let Some(ErrorVariant(variant)) = response.extensions().get::<ErrorVariant>() {
+        metrics.errors.with_label_values(&[variant]).inc();
+    }
+
+    response
+}
+
+/// Marks a response from the unversioned `/api` aliases as deprecated
+/// (RFC 9745) with the date they go away (RFC 8594), each once configured,
+/// and links the `/api/v1` route that replaces it.
+pub async fn middleware_deprecated(
+    State(app_state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    req: Request,
+    next: Next,
+) -> Response {
+    let config = &app_state.api_config;
+    let path = uri.path();
+    let successor = format!("/api/v1{}", path.strip_prefix("/api").unwrap_or(path));
+    tracing::debug!("Deprecated route {} called, use {}", path, successor);
+
+    let mut response = next.run(req).await;
+    let headers = response.headers_mut();
+
+    // the dates are only advertised once an operator has set them
+    let deprecation = config
+        .legacy_deprecated_at
+        .map(|at| (DEPRECATION, format!("@{}", at.timestamp())));
+    let sunset = config
+        .legacy_sunset_at
+        .map(|at| (SUNSET, at.format("%a, %d %b %Y %H:%M:%S GMT").to_string()));
+    let link = (LINK, format!(r#"<{}>; rel="successor-version""#, successor));
+
+    for (name, value) in [deprecation, sunset, Some(link)].into_iter().flatten() {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    }
+
+    response
+}
+
+/// Sheds the request with 503 when too many are already in flight and
+/// answers 504 once it runs past the request timeout. Dropping the handler
+/// cancels whatever it was waiting on, open transactions roll back.
+pub async fn middleware_limits(
+    State(limits): State<Arc<RequestLimits>>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let Ok(_permit) = limits.in_flight.try_acquire() else {
+        tracing::warn!("Too many requests in flight, shedding {}", req.uri().path());
+        return Err(AppError::Overloaded);
+    };
+
+    tokio::time::timeout(limits.request_timeout, next.run(req))
+        .await
+        .map_err(|_| {
+            tracing::warn!("Request timed out after {:?}", limits.request_timeout);
+            AppError::RequestTimeout
+        })
+}
+
+/// Token bucket rate limit for `group`, per signed in user or else per
+/// client IP. Layer it inside `middleware_auth` so it sees the user. When the
+/// store fails the request goes through, rate limiting is not worth an outage.
+pub async fn middleware_rate_limit(
+    State((app_state, group)): State<(AppState, RouteGroup)>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let client = match req.extensions().get::<AuthenticatedUser>() {
+        Some(user) => Some(Client::User(user.user_id)),
+        None => request_ip(req.extensions()).map(Client::Ip),
+    };
+    let Some(client) = client else {
+        return next.run(req).await;
+    };
+
+    let decision = match app_state.rate_limiter.check(group, client).await {
+        Ok(Some(decision)) => decision,
+        Ok(None) => return next.run(req).await,
+        Err(e) => {
+            tracing::warn!("Rate limit check failed, not limiting: {}", e.chain());
+            return next.run(req).await;
+        }
+    };
+
+    let mut response = match decision.allowed {
+        true => next.run(req).await,
+        false => AppError::RateLimited {
+            retry_after: decision.retry_after,
+        }
+        .into_response(),
+    };
+    // behind auth the request is limited twice, the per user bucket is reported
+    for (name, value) in decision.headers() {
+        response.headers_mut().entry(name).or_insert(value);
+    }
+
+    response
+}
+
+/// Keeps personal access tokens away from routes that manage the account
+/// itself (tokens, MFA, logout). Must run after `middleware_auth`.
+pub async fn middleware_require_session(req: Request, next: Next) -> Result<Response, AppError> {
+    let is_access_token = req
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .is_none_or(AuthenticatedUser::is_access_token);
+
+    if is_access_token {
+        tracing::warn!(
+            "Access token used on session-only route {}",
+            req.uri().path()
+        );
+        return Err(AppError::Unauthorized);
+    }
 
     Ok(next.run(req).await)
 }
+
+/// Guards a whole router with [`RequireRole<Admin>`]. Must run after
+/// `middleware_auth`.
+pub async fn middleware_require_admin(
+    _admin: RequireRole<Admin>,
+    req: Request,
+    next: Next,
+) -> Response {
+    next.run(req).await
+}
+
+/// Extracts the peer address and `User-Agent` of a request, both are optional
+/// so this never rejects.
+impl<S: Send + Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(client_info(parts))
+    }
+}
+
+fn client_info(parts: &Parts) -> ClientInfo {
+    let ip_address = request_ip(&parts.extensions);
+
+    let user_agent = parts
+        .headers
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    ClientInfo {
+        ip_address,
+        user_agent,
+    }
+}
+
+/// Resolves the client address once per request, behind trusted proxies
+/// from the forwarded headers, see `client_ip::resolve`.
+pub async fn middleware_client_ip(
+    State(trusted_proxies): State<Arc<[IpNetwork]>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    if let Some(ConnectInfo(peer)) = req.extensions().get::<ConnectInfo<SocketAddr>>().copied() {
+        let ip = client_ip::resolve(peer.ip(), req.headers(), &trusted_proxies);
+        req.extensions_mut().insert(ClientIp(ip));
+    }
+
+    next.run(req).await
+}
+
+// the resolved client address, the peer when `middleware_client_ip` didn't run
+fn request_ip(extensions: &Extensions) -> Option<IpAddr> {
+    match extensions.get::<ClientIp>() {
+        Some(ClientIp(ip)) => Some(*ip),
+        None => extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip()),
+    }
+}
@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::ipnetwork::IpNetwork;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{audit::AuditEvent, task::Task, user::UserProfile};
+
+// Re-authentication for destructive account actions, `code` is required
+// when TOTP is enabled. Users without a password (signed up through OIDC)
+// leave it out and confirm with their TOTP code, or without one, by
+// signing in again shortly before.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ReauthenticatePayload {
+    pub password: Option<String>,
+    pub code: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct DBReauthUser {
+    pub password_hash: String,
+    pub has_password: bool,
+    pub totp_enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AccountDeletionResponse {
+    pub deletion_scheduled_at: DateTime<Utc>,
+}
+
+/// Everything we hold about a user, served by `GET /api/v1/me/export`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AccountExport {
+    pub exported_at: DateTime<Utc>,
+    pub profile: UserProfile,
+    pub tasks: Vec<Task>,
+    pub sessions: Vec<ExportedSession>,
+    pub access_tokens: Vec<ExportedAccessToken>,
+    pub identities: Vec<ExportedIdentity>,
+    pub recovery_codes: ExportedRecoveryCodes,
+    pub audit_events: Vec<AuditEvent>,
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize, ToSchema)]
+pub struct ExportedSession {
+    pub id: Uuid,
+    #[serde(serialize_with = "serialize_ip")]
+    #[schema(value_type = Option<String>)]
+    pub ip_address: Option<IpNetwork>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+// secrets and hashes are never exported
+#[derive(sqlx::FromRow, Debug, Serialize, ToSchema)]
+pub struct ExportedAccessToken {
+    pub id: i64,
+    pub name: String,
+    pub token_prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize, ToSchema)]
+pub struct ExportedIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_login_at: Option<DateTime<Utc>>,
+}
+
+#[derive(sqlx::FromRow, Debug, Serialize, ToSchema)]
+pub struct ExportedRecoveryCodes {
+    pub total: i64,
+    pub unused: i64,
+}
+
+fn serialize_ip<S: serde::Serializer>(
+    ip: &Option<IpNetwork>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match ip {
+        Some(ip) => serializer.serialize_some(&ip.ip().to_string()),
+        None => serializer.serialize_none(),
+    }
+}
@@ -0,0 +1,59 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
+
+use super::user::Role;
+
+#[derive(sqlx::FromRow, Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AdminUserSummary {
+    pub id: i64,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub role: Role,
+    pub totp_enabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub disabled_at: Option<DateTime<Utc>>,
+    pub deletion_scheduled_at: Option<DateTime<Utc>>,
+    // most recent activity over all sessions, None if never signed in
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+/// Filters for `GET /api/v1/admin/users`, newest users first. Pass the last `id`
+/// seen as `before_id` to page further back.
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListUsersQuery {
+    // case insensitive substring of the username or display name
+    pub search: Option<String>,
+    pub role: Option<Role>,
+    pub disabled: Option<bool>,
+    pub before_id: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PasswordResetResponse {
+    // shown once, the user should change it after signing in
+    pub temporary_password: String,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UserStats {
+    pub total: i64,
+    pub admins: i64,
+    pub disabled: i64,
+    pub pending_deletion: i64,
+    pub with_mfa: i64,
+    pub created_last_7_days: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SystemStats {
+    pub users: UserStats,
+    pub active_sessions: i64,
+    pub active_access_tokens: i64,
+    pub tasks_total: i64,
+    pub tasks_by_status: HashMap<String, i64>,
+    pub failed_logins_last_24_hours: i64,
+}
@@ -0,0 +1,141 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::ipnetwork::IpNetwork;
+use utoipa::{IntoParams, ToSchema};
+
+use super::session::ClientInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum AuditEventType {
+    SignUp,
+    Login,
+    // password accepted, second factor still outstanding
+    MfaChallenge,
+    MfaLogin,
+    OidcLogin,
+    TokenRejected,
+    UsernameChanged,
+    AccountDeletionRequested,
+    AccountDeletionCancelled,
+    AccountDeleted,
+    DataExported,
+    AccountDisabled,
+    AccountEnabled,
+    PasswordReset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    Failure,
+}
+
+/// An event about to be written to the audit log.
+#[derive(Debug, Clone)]
+pub struct NewAuditEvent {
+    pub event_type: AuditEventType,
+    pub outcome: AuditOutcome,
+    pub user_id: Option<i64>,
+    pub username: Option<String>,
+    pub reason: Option<String>,
+    pub client: ClientInfo,
+}
+
+impl NewAuditEvent {
+    pub fn success(event_type: AuditEventType, client: &ClientInfo) -> Self {
+        Self {
+            event_type,
+            outcome: AuditOutcome::Success,
+            user_id: None,
+            username: None,
+            reason: None,
+            client: client.clone(),
+        }
+    }
+
+    pub fn failure(event_type: AuditEventType, client: &ClientInfo, reason: impl ToString) -> Self {
+        Self {
+            outcome: AuditOutcome::Failure,
+            reason: Some(reason.to_string()),
+            ..Self::success(event_type, client)
+        }
+    }
+
+    pub fn user_id(mut self, user_id: i64) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    pub fn username(mut self, username: &str) -> Self {
+        self.username = Some(username.to_string());
+        self
+    }
+
+    // extra context on a success, e.g. which admin acted
+    pub fn reason(mut self, reason: impl ToString) -> Self {
+        self.reason = Some(reason.to_string());
+        self
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct DBAuditEvent {
+    pub id: i64,
+    pub event_type: AuditEventType,
+    pub outcome: AuditOutcome,
+    pub user_id: Option<i64>,
+    pub username: Option<String>,
+    pub ip_address: Option<IpNetwork>,
+    pub user_agent: Option<String>,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub event_type: AuditEventType,
+    pub outcome: AuditOutcome,
+    pub user_id: Option<i64>,
+    pub username: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<DBAuditEvent> for AuditEvent {
+    fn from(event: DBAuditEvent) -> Self {
+        Self {
+            id: event.id,
+            event_type: event.event_type,
+            outcome: event.outcome,
+            user_id: event.user_id,
+            username: event.username,
+            ip_address: event.ip_address.map(|ip| ip.ip().to_string()),
+            user_agent: event.user_agent,
+            reason: event.reason,
+            created_at: event.created_at,
+        }
+    }
+}
+
+/// Filters for `GET /api/v1/admin/audit`, all optional. Results are newest
+/// first, pass the last `id` seen as `before_id` to page further back.
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    pub event_type: Option<AuditEventType>,
+    pub outcome: Option<AuditOutcome>,
+    pub user_id: Option<i64>,
+    pub username: Option<String>,
+    pub ip_address: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub before_id: Option<i64>,
+    pub limit: Option<i64>,
+}
@@ -0,0 +1,33 @@
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthCheck {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub latency_ms: f64,
+}
+
+/// Body of `/healthz` and `/readyz`, `status` is `fail` when any check failed.
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub status: CheckStatus,
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    pub fn new(checks: Vec<HealthCheck>) -> Self {
+        let status = match checks.iter().all(|check| check.status == CheckStatus::Pass) {
+            true => CheckStatus::Pass,
+            false => CheckStatus::Fail,
+        };
+
+        Self { status, checks }
+    }
+}
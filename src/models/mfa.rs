@@ -0,0 +1,47 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TotpEnrollmentResponse {
+    pub secret: String,
+    // otpauth:// URI, this is also the payload to render as a QR code
+    pub otpauth_url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MfaCodePayload {
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RecoveryCodesResponse {
+    // shown exactly once, only hashes are stored
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MfaLoginPayload {
+    pub mfa_token: String,
+    // either a current TOTP code or one of the recovery codes
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct MfaChallengeResponse {
+    pub mfa_token: String,
+    pub expires_in: i64,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct DBMfaQuery {
+    pub id: i64,
+    pub username: String,
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct DBRecoveryCode {
+    pub id: i64,
+    pub code_hash: String,
+}
@@ -1,2 +1,10 @@
+pub mod account;
+pub mod admin;
+pub mod audit;
+pub mod health;
+pub mod mfa;
+pub mod oidc;
+pub mod session;
 pub mod task;
+pub mod token;
 pub mod user;
@@ -0,0 +1,35 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AuthorizationUrlResponse {
+    // send the browser here to sign in with the identity provider
+    pub authorization_url: String,
+}
+
+// query string the provider appends to the redirect URI
+#[derive(Debug, Clone, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CallbackParams {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct DBLoginState {
+    pub nonce: String,
+    pub code_verifier: String,
+    pub link_user_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+// the local user an identity signs in as
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct DBLinkedUser {
+    pub id: i64,
+    pub username: String,
+    pub totp_enabled: bool,
+}
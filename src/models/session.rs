@@ -0,0 +1,55 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::types::ipnetwork::IpNetwork;
+use std::net::IpAddr;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Where a login came from, recorded on the session it creates.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip_address: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct DBSession {
+    pub id: Uuid,
+    pub ip_address: Option<IpNetwork>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct DBSessionState {
+    pub active: bool,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Session {
+    pub id: Uuid,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    // true for the session the request was made with
+    pub current: bool,
+}
+
+impl Session {
+    pub fn from_db(session: DBSession, current_session_id: Option<Uuid>) -> Self {
+        Self {
+            id: session.id,
+            ip_address: session.ip_address.map(|ip| ip.ip().to_string()),
+            user_agent: session.user_agent,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+            expires_at: session.expires_at,
+            current: current_session_id == Some(session.id),
+        }
+    }
+}
@@ -1,11 +1,12 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use utoipa::ToSchema;
 
 use ::sqlx::{FromRow, Type};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct CreateTaskPayload {
     pub title: String,
     pub description: Option<String>,
@@ -13,7 +14,7 @@ pub struct CreateTaskPayload {
     pub status: Option<TaskStatus>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UpdateTaskPayload {
     pub title: Option<String>,
     pub description: Option<String>,
@@ -21,7 +22,7 @@ pub struct UpdateTaskPayload {
     pub due_date: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Type)]
+#[derive(Debug, Serialize, Deserialize, Type, ToSchema)]
 #[sqlx(type_name = "varchar")] // Tells sqlx it is stored as varchar
 #[serde(rename_all = "lowercase")] // serializes to "pending" not PENDING
 pub enum TaskStatus {
@@ -41,7 +42,7 @@ impl fmt::Display for TaskStatus {
     }
 }
 
-#[derive(Debug, FromRow, Serialize, Deserialize)]
+#[derive(Debug, FromRow, Serialize, Deserialize, ToSchema)]
 pub struct Task {
     pub id: Uuid,
     pub title: String,
@@ -53,7 +54,7 @@ pub struct Task {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(FromRow, Debug, Serialize, Deserialize)]
+#[derive(FromRow, Debug, Serialize, Deserialize, ToSchema)]
 pub struct TasksResponse {
     pub title: String,
     pub description: Option<String>,
@@ -0,0 +1,73 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+use utoipa::ToSchema;
+
+use super::user::Role;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum TokenScope {
+    #[serde(rename = "tasks:read")]
+    TasksRead,
+    #[serde(rename = "tasks:write")]
+    TasksWrite,
+}
+
+impl fmt::Display for TokenScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenScope::TasksRead => write!(f, "tasks:read"),
+            TokenScope::TasksWrite => write!(f, "tasks:write"),
+        }
+    }
+}
+
+impl FromStr for TokenScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tasks:read" => Ok(TokenScope::TasksRead),
+            "tasks:write" => Ok(TokenScope::TasksWrite),
+            other => Err(format!("unknown token scope: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreateAccessTokenPayload {
+    pub name: String,
+    pub scopes: Vec<TokenScope>,
+    // omit for a token that never expires, at most ten years
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AccessToken {
+    pub id: i64,
+    pub name: String,
+    pub token_prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CreatedAccessTokenResponse {
+    // the plaintext token, only ever returned here
+    pub token: String,
+    #[serde(flatten)]
+    pub details: AccessToken,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct DBAccessTokenOwner {
+    // the token's id
+    pub id: i64,
+    pub user_id: i64,
+    pub username: String,
+    pub role: Role,
+    pub scopes: Vec<String>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
@@ -4,15 +4,48 @@
 // created_at TIMESTAMP NOT NULL DEFAULT NOW()
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use utoipa::ToSchema;
+
+use super::{account::ReauthenticatePayload, mfa::MfaChallengeResponse};
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum Role {
+    User,
+    Admin,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct UserProfile {
     pub id: i64,
     pub username: String,
-    pub password_hash: String,
+    pub role: Role,
+    pub display_name: Option<String>,
+    pub time_zone: String,
+    pub locale: String,
+    pub totp_enabled: bool,
     pub created_at: DateTime<Utc>,
+    // set while a deletion request is in its grace period
+    pub deletion_scheduled_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Absent fields are left alone, an empty `display_name` clears it
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct UpdateProfilePayload {
+    pub display_name: Option<String>,
+    pub time_zone: Option<String>,
+    pub locale: Option<String>,
+}
+
+// the password (and TOTP code) are confirmed first, like for account deletion
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ChangeUsernamePayload {
+    pub username: String,
+    #[serde(flatten)]
+    pub reauthentication: ReauthenticatePayload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct SignupAndLoginPayload {
     pub username: String,
     pub password: String,
@@ -23,11 +56,28 @@ pub struct DBUserQuery {
     pub id: i64,
     pub username: String,
     pub password_hash: String,
+    pub totp_enabled: bool,
+}
+
+// A hash with current Argon2 parameters waiting to replace `old_hash`
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct PasswordRehash {
+    pub new_hash: String,
+    pub old_hash: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct LoginResponse {
     pub token: String,
     pub user_id: i64,
     pub username: String,
+    pub role: Role,
+}
+
+// Outcome of a password check, users with TOTP enabled get a challenge instead of a JWT
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum LoginResult {
+    Authenticated(LoginResponse),
+    MfaRequired(MfaChallengeResponse),
 }
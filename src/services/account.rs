@@ -0,0 +1,422 @@
+use crate::{
+    AppState,
+    common::errors::AppError,
+    models::{
+        account,
+        audit::{self, AuditEventType, NewAuditEvent},
+        session::ClientInfo,
+    },
+    services::{audit::AuditService, mfa::MfaService, task::TaskServices, user::UserService},
+};
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+// how recent a sign in has to be to stand in for a password
+const RECENT_LOGIN_MINUTES: i64 = 5;
+
+pub struct AccountService;
+
+impl AccountService {
+    /// Schedules the account for deletion after the configured grace period.
+    ///
+    /// All sessions and access tokens are revoked straight away, signing in
+    /// again during the grace period is allowed so the request can be cancelled.
+    pub async fn request_deletion(
+        app_state: &AppState,
+        user_id: i64,
+        session_id: Option<Uuid>,
+        request: account::ReauthenticatePayload,
+        client: ClientInfo,
+    ) -> Result<account::AccountDeletionResponse, AppError> {
+        tracing::info!("Requesting account deletion");
+
+        if let Err(e) = Self::reauthenticate(app_state, user_id, session_id, &request).await {
+            AuditService::record(
+                app_state,
+                NewAuditEvent::failure(AuditEventType::AccountDeletionRequested, &client, &e)
+                    .user_id(user_id),
+            )
+            .await;
+
+            return Err(e);
+        }
+
+        let grace_period = Duration::days(app_state.account_deletion_config.grace_period_days);
+
+        let mut tx = app_state
+            .pool
+            .begin()
+            .await
+            .map_err(AppError::database("starting account deletion"))?;
+
+        let deletion_scheduled_at = sqlx::query_scalar::<_, DateTime<Utc>>(
+            "UPDATE users SET deletion_scheduled_at = $1
+             WHERE id = $2 AND deletion_scheduled_at IS NULL
+             RETURNING deletion_scheduled_at",
+        )
+        .bind(Utc::now() + grace_period)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::database("scheduling account deletion"))?
+        .ok_or(AppError::AccountDeletionPending)?;
+
+        sqlx::query(
+            "UPDATE sessions SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::database("revoking sessions"))?;
+
+        sqlx::query(
+            "UPDATE personal_access_tokens SET revoked_at = NOW()
+             WHERE user_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::database("revoking access tokens"))?;
+
+        tx.commit()
+            .await
+            .map_err(AppError::database("committing account deletion"))?;
+
+        AuditService::record(
+            app_state,
+            NewAuditEvent::success(AuditEventType::AccountDeletionRequested, &client)
+                .user_id(user_id),
+        )
+        .await;
+
+        tracing::info!("Account deletion scheduled for {}", deletion_scheduled_at);
+
+        Ok(account::AccountDeletionResponse {
+            deletion_scheduled_at,
+        })
+    }
+
+    pub async fn cancel_deletion(
+        app_state: &AppState,
+        user_id: i64,
+        client: ClientInfo,
+    ) -> Result<String, AppError> {
+        tracing::info!("Cancelling account deletion");
+
+        let result = sqlx::query(
+            "UPDATE users SET deletion_scheduled_at = NULL
+             WHERE id = $1 AND deletion_scheduled_at IS NOT NULL",
+        )
+        .bind(user_id)
+        .execute(&app_state.pool)
+        .await
+        .map_err(AppError::database("cancelling account deletion"))?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound);
+        }
+
+        AuditService::record(
+            app_state,
+            NewAuditEvent::success(AuditEventType::AccountDeletionCancelled, &client)
+                .user_id(user_id),
+        )
+        .await;
+
+        Ok("Account deletion cancelled".to_string())
+    }
+
+    /// Hard deletes every account whose grace period is over. Tasks and auth
+    /// data go with the user row through `ON DELETE CASCADE`, audit events
+    /// are kept but lose the user id and username.
+    pub async fn purge_deleted(app_state: &AppState) -> Result<u64, AppError> {
+        let mut tx = app_state
+            .pool
+            .begin()
+            .await
+            .map_err(AppError::database("starting account purge"))?;
+
+        let purged = sqlx::query_scalar::<_, i64>(
+            "SELECT id FROM users WHERE deletion_scheduled_at <= NOW() FOR UPDATE",
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(AppError::database("loading accounts to purge"))?;
+
+        if purged.is_empty() {
+            return Ok(0);
+        }
+
+        sqlx::query("UPDATE audit_events SET username = NULL WHERE user_id = ANY($1)")
+            .bind(&purged)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::database("scrubbing audit log"))?;
+
+        sqlx::query("DELETE FROM users WHERE id = ANY($1)")
+            .bind(&purged)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::database("purging accounts"))?;
+
+        tx.commit()
+            .await
+            .map_err(AppError::database("committing account purge"))?;
+
+        for _ in &purged {
+            AuditService::record(
+                app_state,
+                NewAuditEvent::success(AuditEventType::AccountDeleted, &ClientInfo::default()),
+            )
+            .await;
+        }
+
+        Ok(purged.len() as u64)
+    }
+
+    /// Background loop purging accounts past their grace period, spawned at startup.
+    /// Returns once shutdown starts, a purge already running is finished first.
+    pub async fn run_purge(app_state: AppState) {
+        let mut interval = tokio::time::interval(app_state.account_deletion_config.purge_interval);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = app_state.shutdown.stopped() => break,
+            }
+
+            match Self::purge_deleted(&app_state).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::info!("Purged {} deleted accounts", purged),
+                Err(e) => tracing::error!("Account purge failed: {}", e.chain()),
+            }
+        }
+    }
+
+    pub async fn export(
+        app_state: &AppState,
+        user_id: i64,
+        client: ClientInfo,
+    ) -> Result<account::AccountExport, AppError> {
+        tracing::info!("Exporting account data");
+
+        let profile = UserService::get_profile(app_state, user_id).await?;
+        let tasks = TaskServices::get_tasks(app_state, user_id).await?;
+
+        let sessions = sqlx::query_as::<_, account::ExportedSession>(
+            "SELECT id, ip_address, user_agent, created_at, last_seen_at, expires_at, revoked_at
+             FROM sessions WHERE user_id = $1 ORDER BY created_at",
+        )
+        .bind(user_id)
+        .fetch_all(&app_state.pool)
+        .await
+        .map_err(AppError::database("exporting sessions"))?;
+
+        let access_tokens = sqlx::query_as::<_, account::ExportedAccessToken>(
+            "SELECT id, name, token_prefix, scopes, created_at, last_used_at, expires_at, revoked_at
+             FROM personal_access_tokens WHERE user_id = $1 ORDER BY created_at",
+        )
+        .bind(user_id)
+        .fetch_all(&app_state.pool)
+        .await
+        .map_err(AppError::database("exporting access tokens"))?;
+
+        let identities = sqlx::query_as::<_, account::ExportedIdentity>(
+            "SELECT issuer, subject, email, created_at, last_login_at
+             FROM user_identities WHERE user_id = $1 ORDER BY created_at",
+        )
+        .bind(user_id)
+        .fetch_all(&app_state.pool)
+        .await
+        .map_err(AppError::database("exporting identities"))?;
+
+        let recovery_codes = sqlx::query_as::<_, account::ExportedRecoveryCodes>(
+            "SELECT COUNT(*) AS total, COUNT(*) FILTER (WHERE used_at IS NULL) AS unused
+             FROM recovery_codes WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_one(&app_state.pool)
+        .await
+        .map_err(AppError::database("exporting recovery codes"))?;
+
+        let audit_events = sqlx::query_as::<_, audit::DBAuditEvent>(
+            "SELECT id, event_type, outcome, user_id, username, ip_address, user_agent, reason, created_at
+             FROM audit_events WHERE user_id = $1 ORDER BY id",
+        )
+        .bind(user_id)
+        .fetch_all(&app_state.pool)
+        .await
+        .map_err(AppError::database("exporting audit events"))?
+        .into_iter()
+        .map(audit::AuditEvent::from)
+        .collect();
+
+        AuditService::record(
+            app_state,
+            NewAuditEvent::success(AuditEventType::DataExported, &client).user_id(user_id),
+        )
+        .await;
+
+        Ok(account::AccountExport {
+            exported_at: Utc::now(),
+            profile,
+            tasks,
+            sessions,
+            access_tokens,
+            identities,
+            recovery_codes,
+            audit_events,
+        })
+    }
+
+    /// Password, plus a TOTP or recovery code when two-factor is enabled.
+    ///
+    /// Users without a password confirm with the TOTP or recovery code alone,
+    /// or when they have no second factor either, with `session_id` having
+    /// been signed in within the last few minutes, e.g. with the provider.
+    pub async fn reauthenticate(
+        app_state: &AppState,
+        user_id: i64,
+        session_id: Option<Uuid>,
+        request: &account::ReauthenticatePayload,
+    ) -> Result<(), AppError> {
+        let user = sqlx::query_as::<_, account::DBReauthUser>(
+            "SELECT password_hash, has_password, totp_enabled FROM users WHERE id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&app_state.pool)
+        .await
+        .map_err(AppError::database("loading user for re-authentication"))?
+        .ok_or(AppError::NotFound)?;
+
+        if user.has_password {
+            let password = request.password.as_deref().unwrap_or_default();
+
+            if !app_state
+                .password_utils
+                .verfify_passowrd(password, &user.password_hash)
+                .await
+            {
+                tracing::warn!("Re-authentication failed");
+                return Err(AppError::InvalidUserCredentials);
+            }
+        } else if !user.totp_enabled
+            && !Self::signed_in_recently(app_state, user_id, session_id).await?
+        {
+            tracing::warn!("Re-authentication without a password needs a recent sign in");
+            return Err(AppError::ReauthenticationRequired);
+        }
+
+        if user.totp_enabled {
+            let code = request.code.as_deref().ok_or(AppError::InvalidMfaCode)?;
+            let mfa_user = MfaService::fetch_mfa_user(app_state, user_id).await?;
+
+            MfaService::verify_second_factor(app_state, &mfa_user, code).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn signed_in_recently(
+        app_state: &AppState,
+        user_id: i64,
+        session_id: Option<Uuid>,
+    ) -> Result<bool, AppError> {
+        let Some(session_id) = session_id else {
+            return Ok(false);
+        };
+
+        sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(
+                SELECT 1 FROM sessions
+                WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL AND created_at > $3
+             )",
+        )
+        .bind(session_id)
+        .bind(user_id)
+        .bind(Utc::now() - Duration::minutes(RECENT_LOGIN_MINUTES))
+        .fetch_one(&app_state.pool)
+        .await
+        .map_err(AppError::database("checking for a recent sign in"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{services::session::SessionService, test_support};
+    use sqlx::PgPool;
+
+    const NO_PASSWORD: account::ReauthenticatePayload = account::ReauthenticatePayload {
+        password: None,
+        code: None,
+    };
+
+    async fn passwordless_user(app_state: &AppState) -> (i64, Uuid) {
+        let user_id = test_support::create_user(app_state, "oidc-user").await;
+        sqlx::query("UPDATE users SET has_password = FALSE WHERE id = $1")
+            .bind(user_id)
+            .execute(&app_state.pool)
+            .await
+            .unwrap();
+        let (session_id, _) =
+            SessionService::create_session(app_state, user_id, &ClientInfo::default())
+                .await
+                .unwrap();
+
+        (user_id, session_id)
+    }
+
+    #[sqlx::test]
+    async fn passwordless_user_can_delete_after_signing_in(pool: PgPool) {
+        let app_state = test_support::app_state(pool);
+        let (user_id, session_id) = passwordless_user(&app_state).await;
+
+        let result = AccountService::request_deletion(
+            &app_state,
+            user_id,
+            Some(session_id),
+            NO_PASSWORD,
+            ClientInfo::default(),
+        )
+        .await;
+        assert!(result.is_ok(), "{result:?}");
+    }
+
+    #[sqlx::test]
+    async fn passwordless_user_needs_a_recent_sign_in(pool: PgPool) {
+        let app_state = test_support::app_state(pool);
+        let (user_id, session_id) = passwordless_user(&app_state).await;
+        sqlx::query("UPDATE sessions SET created_at = NOW() - INTERVAL '1 hour' WHERE id = $1")
+            .bind(session_id)
+            .execute(&app_state.pool)
+            .await
+            .unwrap();
+
+        let result =
+            AccountService::reauthenticate(&app_state, user_id, Some(session_id), &NO_PASSWORD)
+                .await;
+        assert!(
+            matches!(result, Err(AppError::ReauthenticationRequired)),
+            "{result:?}"
+        );
+    }
+
+    #[sqlx::test]
+    async fn users_with_a_password_still_need_it(pool: PgPool) {
+        let app_state = test_support::app_state(pool);
+        let user_id = test_support::create_user(&app_state, "alice").await;
+        let (session_id, _) =
+            SessionService::create_session(&app_state, user_id, &ClientInfo::default())
+                .await
+                .unwrap();
+
+        let result =
+            AccountService::reauthenticate(&app_state, user_id, Some(session_id), &NO_PASSWORD)
+                .await;
+        assert!(
+            matches!(result, Err(AppError::InvalidUserCredentials)),
+            "{result:?}"
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use crate::{
+    AppState,
+    common::{errors::AppError, redact, utils::TokenUtils},
+    models::{
+        admin,
+        audit::{AuditEventType, NewAuditEvent},
+        session::ClientInfo,
+    },
+    services::audit::AuditService,
+};
+use std::collections::HashMap;
+
+const DEFAULT_LIST_LIMIT: i64 = 50;
+const MAX_LIST_LIMIT: i64 = 500;
+const TEMPORARY_PASSWORD_LEN: usize = 20;
+
+pub struct AdminService;
+
+impl AdminService {
+    /// Promotes the users listed in `ADMIN_USER_IDS`, run once at startup.
+    /// Matched by id so renaming an account can't pick up the role.
+    pub async fn bootstrap_admins(app_state: &AppState) -> Result<(), AppError> {
+        let user_ids = &app_state.admin_config.user_ids;
+
+        if user_ids.is_empty() {
+            return Ok(());
+        }
+
+        let promoted = sqlx::query_as::<_, (i64, String)>(
+            "UPDATE users SET role = 'admin' WHERE id = ANY($1) AND role <> 'admin'
+             RETURNING id, username",
+        )
+        .bind(user_ids)
+        .fetch_all(&app_state.pool)
+        .await
+        .map_err(AppError::database("promoting admins"))?;
+
+        for (user_id, username) in promoted {
+            tracing::warn!(
+                user_id,
+                "Promoted {} to admin from ADMIN_USER_IDS",
+                redact::pii(&username)
+            );
+        }
+
+        Ok(())
+    }
+
+    pub async fn list_users(
+        app_state: &AppState,
+        query: admin::ListUsersQuery,
+    ) -> Result<Vec<admin::AdminUserSummary>, AppError> {
+        tracing::info!("Listing users");
+
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_LIST_LIMIT)
+            .clamp(1, MAX_LIST_LIMIT);
+        let search = query
+            .search
+            .as_deref()
+            .map(str::trim)
+            .filter(|search| !search.is_empty())
+            .map(|search| format!("%{}%", search.replace('%', "\\%").replace('_', "\\_")));
+
+        sqlx::query_as::<_, admin::AdminUserSummary>(
+            "SELECT users.id, users.username, users.display_name, users.role, users.totp_enabled,
+                    users.created_at AT TIME ZONE 'UTC' AS created_at,
+                    users.disabled_at, users.deletion_scheduled_at,
+                    (SELECT MAX(last_seen_at) FROM sessions WHERE sessions.user_id = users.id) AS last_seen_at
+             FROM users
+             WHERE ($1::TEXT IS NULL OR users.username ILIKE $1 OR users.display_name ILIKE $1)
+               AND ($2::TEXT IS NULL OR users.role = $2)
+               AND ($3::BOOL IS NULL OR (users.disabled_at IS NOT NULL) = $3)
+               AND ($4::BIGINT IS NULL OR users.id < $4)
+             ORDER BY users.id DESC
+             LIMIT $5",
+        )
+        .bind(search)
+        .bind(query.role)
+        .bind(query.disabled)
+        .bind(query.before_id)
+        .bind(limit)
+        .fetch_all(&app_state.pool)
+        .await
+        .map_err(AppError::database("listing users"))
+    }
+
+    /// Disables or re-enables an account. Disabling signs the user out
+    /// everywhere and revokes their access tokens.
+    pub async fn set_disabled(
+        app_state: &AppState,
+        admin_id: i64,
+        user_id: i64,
+        disabled: bool,
+        client: ClientInfo,
+    ) -> Result<String, AppError> {
+        tracing::info!(
+            "Admin {} setting disabled={} on {}",
+            admin_id,
+            disabled,
+            user_id
+        );
+
+        // an admin locking themselves out helps nobody
+        if disabled && admin_id == user_id {
+            return Err(AppError::Unauthorized);
+        }
+
+        let mut tx = app_state
+            .pool
+            .begin()
+            .await
+            .map_err(AppError::database("starting account disable"))?;
+
+        let username = sqlx::query_scalar::<_, String>(
+            "UPDATE users SET disabled_at = CASE WHEN $2 THEN COALESCE(disabled_at, NOW()) END
+             WHERE id = $1
+             RETURNING username",
+        )
+        .bind(user_id)
+        .bind(disabled)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::database("updating account status"))?
+        .ok_or(AppError::NotFound)?;
+
+        if disabled {
+            Self::revoke_credentials(&mut tx, user_id).await?;
+        }
+
+        tx.commit()
+            .await
+            .map_err(AppError::database("committing account status"))?;
+
+        let event_type = match disabled {
+            true => AuditEventType::AccountDisabled,
+            false => AuditEventType::AccountEnabled,
+        };
+        AuditService::record(
+            app_state,
+            NewAuditEvent::success(event_type, &client)
+                .user_id(user_id)
+                .username(&username)
+                .reason(format!("by admin {}", admin_id)),
+        )
+        .await;
+
+        Ok(match disabled {
+            true => "Account disabled".to_string(),
+            false => "Account enabled".to_string(),
+        })
+    }
+
+    /// Replaces the user's password with a random one and signs them out
+    /// everywhere. The temporary password is only returned here.
+    pub async fn reset_password(
+        app_state: &AppState,
+        admin_id: i64,
+        user_id: i64,
+        client: ClientInfo,
+    ) -> Result<admin::PasswordResetResponse, AppError> {
+        tracing::info!("Admin {} resetting password of {}", admin_id, user_id);
+
+        let temporary_password = TokenUtils::random_token(TEMPORARY_PASSWORD_LEN);
+        let password_hash = app_state
+            .password_utils
+            .hash_password(&temporary_password)
+            .await?;
+
+        let mut tx = app_state
+            .pool
+            .begin()
+            .await
+            .map_err(AppError::database("starting password reset"))?;
+
+        let username = sqlx::query_scalar::<_, String>(
+            "UPDATE users SET password_hash = $2 WHERE id = $1 RETURNING username",
+        )
+        .bind(user_id)
+        .bind(&password_hash)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::database("resetting password"))?
+        .ok_or(AppError::NotFound)?;
+
+        Self::revoke_credentials(&mut tx, user_id).await?;
+
+        tx.commit()
+            .await
+            .map_err(AppError::database("committing password reset"))?;
+
+        AuditService::record(
+            app_state,
+            NewAuditEvent::success(AuditEventType::PasswordReset, &client)
+                .user_id(user_id)
+                .username(&username)
+                .reason(format!("by admin {}", admin_id)),
+        )
+        .await;
+
+        Ok(admin::PasswordResetResponse { temporary_password })
+    }
+
+    pub async fn stats(app_state: &AppState) -> Result<admin::SystemStats, AppError> {
+        tracing::info!("Loading system statistics");
+
+        let users = sqlx::query_as::<_, admin::UserStats>(
+            "SELECT COUNT(*) AS total,
+                    COUNT(*) FILTER (WHERE role = 'admin') AS admins,
+                    COUNT(*) FILTER (WHERE disabled_at IS NOT NULL) AS disabled,
+                    COUNT(*) FILTER (WHERE deletion_scheduled_at IS NOT NULL) AS pending_deletion,
+                    COUNT(*) FILTER (WHERE totp_enabled) AS with_mfa,
+                    COUNT(*) FILTER (WHERE created_at > NOW() - INTERVAL '7 days') AS created_last_7_days
+             FROM users",
+        )
+        .fetch_one(&app_state.pool)
+        .await
+        .map_err(AppError::database("loading user stats"))?;
+
+        let (active_sessions, active_access_tokens, failed_logins_last_24_hours) =
+            sqlx::query_as::<_, (i64, i64, i64)>(
+                "SELECT
+                    (SELECT COUNT(*) FROM sessions
+                     WHERE revoked_at IS NULL AND expires_at > NOW()),
+                    (SELECT COUNT(*) FROM personal_access_tokens
+                     WHERE revoked_at IS NULL AND (expires_at IS NULL OR expires_at > NOW())),
+                    (SELECT COUNT(*) FROM audit_events
+                     WHERE event_type IN ('login', 'mfa_login', 'oidc_login')
+                       AND outcome = 'failure'
+                       AND created_at > NOW() - INTERVAL '24 hours')",
+            )
+            .fetch_one(&app_state.pool)
+            .await
+            .map_err(AppError::database("loading session stats"))?;
+
+        let tasks_by_status: HashMap<String, i64> = sqlx::query_as::<_, (String, i64)>(
+            "SELECT status, COUNT(*) FROM tasks GROUP BY status",
+        )
+        .fetch_all(&app_state.pool)
+        .await
+        .map_err(AppError::database("loading task stats"))?
+        .into_iter()
+        .collect();
+
+        Ok(admin::SystemStats {
+            users,
+            active_sessions,
+            active_access_tokens,
+            tasks_total: tasks_by_status.values().sum(),
+            tasks_by_status,
+            failed_logins_last_24_hours,
+        })
+    }
+
+    async fn revoke_credentials(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        user_id: i64,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            "UPDATE sessions SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await
+        .map_err(AppError::database("revoking sessions"))?;
+
+        sqlx::query(
+            "UPDATE personal_access_tokens SET revoked_at = NOW()
+             WHERE user_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await
+        .map_err(AppError::database("revoking access tokens"))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{account::ReauthenticatePayload, user},
+        services::user::UserService,
+        test_support::{self, PASSWORD},
+    };
+    use sqlx::PgPool;
+
+    async fn role(app_state: &AppState, user_id: i64) -> String {
+        sqlx::query_scalar("SELECT role FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_one(&app_state.pool)
+            .await
+            .unwrap()
+    }
+
+    async fn rename(app_state: &AppState, user_id: i64, username: &str) {
+        let request = user::ChangeUsernamePayload {
+            username: username.to_string(),
+            reauthentication: ReauthenticatePayload {
+                password: Some(PASSWORD.to_string()),
+                code: None,
+            },
+        };
+
+        UserService::change_username(app_state, user_id, None, request, ClientInfo::default())
+            .await
+            .unwrap();
+    }
+
+    #[sqlx::test]
+    async fn renaming_into_an_admins_name_does_not_promote(pool: PgPool) {
+        let mut app_state = test_support::app_state(pool);
+        let admin_id = test_support::create_user(&app_state, "root").await;
+        let mallory_id = test_support::create_user(&app_state, "mallory").await;
+        app_state.admin_config.user_ids = vec![admin_id];
+
+        rename(&app_state, admin_id, "root2").await;
+        rename(&app_state, mallory_id, "root").await;
+        AdminService::bootstrap_admins(&app_state).await.unwrap();
+
+        assert_eq!(role(&app_state, admin_id).await, "admin");
+        assert_eq!(role(&app_state, mallory_id).await, "user");
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{
+    AppState,
+    common::errors::AppError,
+    models::audit::{self, AuditEvent, AuditQuery, NewAuditEvent},
+};
+use chrono::{Duration, Utc};
+use sqlx::types::ipnetwork::IpNetwork;
+
+const DEFAULT_QUERY_LIMIT: i64 = 100;
+const MAX_QUERY_LIMIT: i64 = 1000;
+const MAX_USER_AGENT_LEN: usize = 512;
+
+pub struct AuditService;
+
+impl AuditService {
+    /// Writes an event to the audit log.
+    ///
+    /// A failing write is logged but never fails the request that caused it.
+    pub async fn record(app_state: &AppState, event: NewAuditEvent) {
+        app_state
+            .metrics
+            .record_audit_event(event.event_type, event.outcome);
+
+        let user_agent = event
+            .client
+            .user_agent
+            .as_deref()
+            .map(|ua| ua.chars().take(MAX_USER_AGENT_LEN).collect::<String>());
+
+        let result = sqlx::query(
+            "INSERT INTO audit_events
+                (event_type, outcome, user_id, username, ip_address, user_agent, reason)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+        .bind(event.event_type)
+        .bind(event.outcome)
+        .bind(event.user_id)
+        .bind(&event.username)
+        .bind(event.client.ip_address.map(IpNetwork::from))
+        .bind(user_agent)
+        .bind(&event.reason)
+        .execute(&app_state.pool)
+        .await;
+
+        if let Err(e) = result {
+            tracing::error!(
+                "Database error writing audit event {:?}: {:?}",
+                event.event_type,
+                e
+            );
+        }
+    }
+
+    pub async fn query(
+        app_state: &AppState,
+        query: AuditQuery,
+    ) -> Result<Vec<AuditEvent>, AppError> {
+        tracing::info!("Querying audit log");
+
+        let ip_address = query
+            .ip_address
+            .as_deref()
+            .map(str::parse::<IpNetwork>)
+            .transpose()
+            .map_err(|_| AppError::InvalidAuditQuery)?;
+
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_QUERY_LIMIT)
+            .clamp(1, MAX_QUERY_LIMIT);
+
+        let events = sqlx::query_as::<_, audit::DBAuditEvent>(
+            "SELECT id, event_type, outcome, user_id, username, ip_address, user_agent, reason, created_at
+             FROM audit_events
+             WHERE ($1::TEXT IS NULL OR event_type = $1)
+               AND ($2::TEXT IS NULL OR outcome = $2)
+               AND ($3::BIGINT IS NULL OR user_id = $3)
+               AND ($4::TEXT IS NULL OR username = $4)
+               AND ($5::INET IS NULL OR ip_address <<= $5)
+               AND ($6::TIMESTAMPTZ IS NULL OR created_at >= $6)
+               AND ($7::TIMESTAMPTZ IS NULL OR created_at < $7)
+               AND ($8::BIGINT IS NULL OR id < $8)
+             ORDER BY id DESC
+             LIMIT $9",
+        )
+        .bind(query.event_type)
+        .bind(query.outcome)
+        .bind(query.user_id)
+        .bind(query.username)
+        .bind(ip_address)
+        .bind(query.since)
+        .bind(query.until)
+        .bind(query.before_id)
+        .bind(limit)
+        .fetch_all(&app_state.pool)
+        .await
+        .map_err(AppError::database("querying audit log"))?;
+
+        Ok(events.into_iter().map(AuditEvent::from).collect())
+    }
+
+    /// Deletes events older than the configured retention, returns how many.
+    pub async fn purge_expired(app_state: &AppState) -> Result<u64, AppError> {
+        let Some(retention_days) = app_state.audit_config.retention_days else {
+            return Ok(0);
+        };
+
+        let result = sqlx::query("DELETE FROM audit_events WHERE created_at < $1")
+            .bind(Utc::now() - Duration::days(retention_days))
+            .execute(&app_state.pool)
+            .await
+            .map_err(AppError::database("purging audit log"))?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Background loop applying the retention policy, spawned at startup.
+    /// Returns once shutdown starts, a purge already running is finished first.
+    pub async fn run_retention(app_state: AppState) {
+        let mut interval = tokio::time::interval(app_state.audit_config.purge_interval);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = app_state.shutdown.stopped() => break,
+            }
+
+            match Self::purge_expired(&app_state).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::info!("Purged {} expired audit events", purged),
+                Err(e) => tracing::error!("Audit log retention failed: {}", e.chain()),
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use crate::{
+    AppState,
+    database::migrations,
+    models::health::{CheckStatus, HealthCheck, HealthReport},
+};
+use std::{future::Future, time::Instant};
+
+pub struct HealthService;
+
+impl HealthService {
+    /// Liveness only, if we can answer at all the process is fine.
+    pub fn liveness() -> HealthReport {
+        HealthReport::new(vec![HealthCheck {
+            name: "process",
+            status: CheckStatus::Pass,
+            latency_ms: 0.0,
+        }])
+    }
+
+    /// Whether this instance should get traffic: not shutting down, the pool
+    /// still has room, the database answers within the readiness timeout and
+    /// the schema is fully migrated.
+    ///
+    /// The report only says which checks failed, the reasons go to the log.
+    pub async fn readiness(app_state: &AppState) -> HealthReport {
+        let shutdown = Self::check(app_state, "shutdown", async {
+            match app_state.shutdown.is_ready() {
+                true => Ok(()),
+                false => Err("shutting down".to_string()),
+            }
+        })
+        .await;
+
+        // before anything acquires, an exhausted pool would stall the other checks
+        let pool = Self::check(app_state, "pool", async {
+            let pool = &app_state.pool;
+            let size = pool.size();
+            let idle = pool.num_idle() as u32;
+            let max = pool.options().get_max_connections();
+
+            match idle > 0 || size < max {
+                true => Ok(()),
+                false => Err(format!("exhausted, {size}/{max} connections, {idle} idle")),
+            }
+        })
+        .await;
+
+        let database = Self::check(app_state, "database", async {
+            let mut conn = Self::connection(app_state, &pool).await?;
+
+            sqlx::query("SELECT 1")
+                .execute(&mut *conn)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+        .await;
+
+        let migrations = Self::check(app_state, "migrations", async {
+            let mut conn = Self::connection(app_state, &pool).await?;
+            let applied = migrations::applied_version(&mut conn)
+                .await
+                .map_err(|e| e.to_string())?;
+            let expected = migrations::expected_version();
+
+            match applied >= expected {
+                true => Ok(()),
+                false => Err(format!("at version {applied}, expected {expected}")),
+            }
+        })
+        .await;
+
+        HealthReport::new(vec![shutdown, pool, database, migrations])
+    }
+
+    // only waits for a connection when the pool check saw room for one
+    async fn connection(
+        app_state: &AppState,
+        pool: &HealthCheck,
+    ) -> Result<sqlx::pool::PoolConnection<sqlx::Postgres>, String> {
+        if pool.status == CheckStatus::Fail {
+            return Err("skipped, no free connection".to_string());
+        }
+
+        app_state.pool.acquire().await.map_err(|e| e.to_string())
+    }
+
+    // runs one check under the readiness timeout and times it
+    async fn check(
+        app_state: &AppState,
+        name: &'static str,
+        check: impl Future<Output = Result<(), String>>,
+    ) -> HealthCheck {
+        let started = Instant::now();
+        let timeout = app_state.health_config.readiness_timeout;
+        let result = tokio::time::timeout(timeout, check)
+            .await
+            .unwrap_or_else(|_| Err(format!("timed out after {}ms", timeout.as_millis())));
+        let latency_ms = started.elapsed().as_micros() as f64 / 1000.0;
+
+        let status = match result {
+            Ok(()) => CheckStatus::Pass,
+            Err(reason) => {
+                tracing::warn!("Readiness check {} failed: {}", name, reason);
+                CheckStatus::Fail
+            }
+        };
+
+        HealthCheck {
+            name,
+            status,
+            latency_ms,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use sqlx::{PgPool, postgres::PgPoolOptions};
+
+    fn statuses(report: &HealthReport) -> Vec<(&'static str, CheckStatus)> {
+        report
+            .checks
+            .iter()
+            .map(|check| (check.name, check.status))
+            .collect()
+    }
+
+    #[sqlx::test]
+    async fn report_carries_no_details(pool: PgPool) {
+        let app_state = test_support::app_state(pool);
+
+        let report = HealthService::readiness(&app_state).await;
+        assert_eq!(report.status, CheckStatus::Pass);
+        let body = serde_json::to_value(&report).unwrap();
+        for check in body["checks"].as_array().unwrap() {
+            let mut keys: Vec<_> = check.as_object().unwrap().keys().collect();
+            keys.sort();
+            assert_eq!(keys, ["latency_ms", "name", "status"]);
+        }
+    }
+
+    #[sqlx::test]
+    async fn exhausted_pool_fails_without_waiting(pool: PgPool) {
+        let small = PgPoolOptions::new()
+            .max_connections(1)
+            .connect_with(pool.connect_options().as_ref().clone())
+            .await
+            .unwrap();
+        let app_state = test_support::app_state(small.clone());
+        let _held = small.acquire().await.unwrap();
+
+        let started = Instant::now();
+        let report = HealthService::readiness(&app_state).await;
+        assert!(started.elapsed() < app_state.health_config.readiness_timeout);
+        assert_eq!(
+            statuses(&report),
+            [
+                ("shutdown", CheckStatus::Pass),
+                ("pool", CheckStatus::Fail),
+                ("database", CheckStatus::Fail),
+                ("migrations", CheckStatus::Fail),
+            ]
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{AppState, common::errors::AppError};
+use std::time::{Duration, Instant};
+
+// the task counts scan the whole table, so they are refreshed on a timer
+// instead of on every scrape
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+pub struct MetricsService;
+
+impl MetricsService {
+    /// Refreshes the pool gauges, which are free to read, and renders
+    /// everything. Never touches the database.
+    pub fn scrape(app_state: &AppState) -> String {
+        let metrics = &app_state.metrics;
+        let pool = &app_state.pool;
+
+        let idle = pool.num_idle() as i64;
+        metrics
+            .pool_connections
+            .with_label_values(&["idle"])
+            .set(idle);
+        metrics
+            .pool_connections
+            .with_label_values(&["in_use"])
+            .set(pool.size() as i64 - idle);
+        metrics
+            .pool_max_connections
+            .set(pool.options().get_max_connections() as i64);
+
+        metrics.render()
+    }
+
+    /// Counts tasks by status. The wait for its connection is a periodic
+    /// probe of pool contention, taken the same way any request waits.
+    pub async fn refresh(app_state: &AppState) -> Result<(), AppError> {
+        let metrics = &app_state.metrics;
+
+        let started = Instant::now();
+        let mut conn = app_state
+            .pool
+            .acquire()
+            .await
+            .map_err(AppError::database("acquiring connection for metrics"))?;
+        metrics
+            .pool_acquire_wait
+            .observe(started.elapsed().as_secs_f64());
+
+        let tasks_by_status = sqlx::query_as::<_, (String, i64)>(
+            "SELECT status, COUNT(*) FROM tasks GROUP BY status",
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(AppError::database("counting tasks for metrics"))?;
+
+        // statuses that no longer have tasks should drop to 0, not keep their last value
+        metrics.tasks.reset();
+        for (status, count) in tasks_by_status {
+            metrics.tasks.with_label_values(&[&status]).set(count);
+        }
+
+        Ok(())
+    }
+
+    pub async fn run_refresh(app_state: AppState) {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = app_state.shutdown.stopped() => break,
+            }
+
+            if let Err(e) = Self::refresh(&app_state).await {
+                tracing::error!("Refreshing metrics failed: {}", e.chain());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn task_counts_change_only_on_refresh(pool: PgPool) {
+        let app_state = test_support::app_state(pool);
+        let user_id = test_support::create_user(&app_state, "alice").await;
+        let add_task = || async {
+            sqlx::query(
+                "INSERT INTO tasks (title, status, due_date, user_id) VALUES ('t', 'pending', NOW(), $1)",
+            )
+            .bind(user_id)
+            .execute(&app_state.pool)
+            .await
+            .unwrap();
+        };
+
+        add_task().await;
+        MetricsService::refresh(&app_state).await.unwrap();
+        add_task().await;
+
+        let scraped = MetricsService::scrape(&app_state);
+        assert!(
+            scraped.contains("tasks_backend_tasks{status=\"pending\"} 1"),
+            "{scraped}"
+        );
+
+        MetricsService::refresh(&app_state).await.unwrap();
+        let scraped = MetricsService::scrape(&app_state);
+        assert!(
+            scraped.contains("tasks_backend_tasks{status=\"pending\"} 2"),
+            "{scraped}"
+        );
+    }
+}
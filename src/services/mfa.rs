@@ -0,0 +1,596 @@
+use crate::{
+    AppState,
+    common::{errors::AppError, jwt, totp::TotpUtils},
+    models::{mfa, user},
+};
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+pub struct MfaService;
+
+impl MfaService {
+    /// Starts TOTP enrollment by storing a new, not yet enabled, secret.
+    ///
+    /// Calling it again before confirming replaces the pending secret.
+    pub async fn enroll_totp(
+        app_state: &AppState,
+        user_id: i64,
+    ) -> Result<mfa::TotpEnrollmentResponse, AppError> {
+        tracing::info!("Starting TOTP enrollment");
+
+        let user = Self::fetch_mfa_user(app_state, user_id).await?;
+
+        if user.totp_enabled {
+            return Err(AppError::MfaAlreadyEnabled);
+        }
+
+        let secret = TotpUtils::generate_secret();
+        let otpauth_url =
+            TotpUtils::otpauth_url(&secret, &app_state.mfa_config.issuer, &user.username)?;
+
+        sqlx::query("UPDATE users SET totp_secret = $1, totp_last_step = NULL WHERE id = $2")
+            .bind(&secret)
+            .bind(user_id)
+            .execute(&app_state.pool)
+            .await
+            .map_err(AppError::database("storing TOTP secret"))?;
+
+        Ok(mfa::TotpEnrollmentResponse {
+            secret,
+            otpauth_url,
+        })
+    }
+
+    /// Confirms a pending enrollment with a code from the authenticator app,
+    /// enables TOTP and hands out a fresh set of recovery codes.
+    pub async fn confirm_totp(
+        app_state: &AppState,
+        user_id: i64,
+        payload: mfa::MfaCodePayload,
+    ) -> Result<mfa::RecoveryCodesResponse, AppError> {
+        tracing::info!("Confirming TOTP enrollment");
+
+        let user = Self::fetch_mfa_user(app_state, user_id).await?;
+
+        if user.totp_enabled {
+            return Err(AppError::MfaAlreadyEnabled);
+        }
+
+        let secret = user.totp_secret.ok_or(AppError::MfaNotPending)?;
+
+        let step =
+            TotpUtils::verify_code(&secret, payload.code.trim(), Utc::now().timestamp() as u64)
+                .ok_or_else(|| {
+                    tracing::warn!("Invalid TOTP code during enrollment");
+                    AppError::InvalidMfaCode
+                })?;
+
+        let recovery_codes = TotpUtils::generate_recovery_codes(RECOVERY_CODE_COUNT);
+        let mut code_hashes = Vec::with_capacity(recovery_codes.len());
+        for code in &recovery_codes {
+            code_hashes.push(app_state.password_utils.hash_password(code).await?);
+        }
+
+        let mut tx = app_state
+            .pool
+            .begin()
+            .await
+            .map_err(AppError::database("starting TOTP confirmation"))?;
+
+        sqlx::query("UPDATE users SET totp_enabled = TRUE, totp_last_step = $1 WHERE id = $2")
+            .bind(step as i64)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::database("enabling TOTP"))?;
+
+        sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::database("clearing old recovery codes"))?;
+
+        sqlx::query(
+            "INSERT INTO recovery_codes (user_id, code_hash)
+             SELECT $1, UNNEST($2::TEXT[])",
+        )
+        .bind(user_id)
+        .bind(&code_hashes)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::database("storing recovery codes"))?;
+
+        tx.commit()
+            .await
+            .map_err(AppError::database("committing TOTP confirmation"))?;
+
+        tracing::info!("TOTP enabled");
+
+        Ok(mfa::RecoveryCodesResponse { recovery_codes })
+    }
+
+    /// Opens a challenge for a user whose first factor passed and hands out
+    /// the pending token for it, its `jti` is the challenge id.
+    pub async fn create_challenge(
+        app_state: &AppState,
+        user_id: i64,
+        rehash: Option<user::PasswordRehash>,
+    ) -> Result<mfa::MfaChallengeResponse, AppError> {
+        let expires_in = app_state.mfa_config.pending_token_expiration;
+        let challenge_id = Uuid::new_v4();
+
+        // the user's own dead challenges, cheaper than a background sweep
+        sqlx::query("DELETE FROM mfa_challenges WHERE user_id = $1 AND expires_at <= NOW()")
+            .bind(user_id)
+            .execute(&app_state.pool)
+            .await
+            .map_err(AppError::database("pruning MFA challenges"))?;
+
+        let (new_password_hash, old_password_hash) = rehash
+            .map(|rehash| (rehash.new_hash, rehash.old_hash))
+            .unzip();
+
+        sqlx::query(
+            "INSERT INTO mfa_challenges (id, user_id, expires_at, new_password_hash, old_password_hash)
+             VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(challenge_id)
+        .bind(user_id)
+        .bind(Utc::now() + Duration::seconds(expires_in))
+        .bind(new_password_hash)
+        .bind(old_password_hash)
+        .execute(&app_state.pool)
+        .await
+        .map_err(AppError::database("creating MFA challenge"))?;
+
+        let mfa_token =
+            jwt::generate_mfa_pending_token(user_id, challenge_id, expires_in, &app_state.jwt_keys)
+                .map_err(|_| {
+                    tracing::error!("Error generating mfa token for user {}", user_id);
+
+                    AppError::JWTCreationFailed
+                })?;
+
+        Ok(mfa::MfaChallengeResponse {
+            mfa_token,
+            expires_in,
+        })
+    }
+
+    /// Answers the challenge behind a pending token. Each code tried uses up
+    /// one of its attempts, once they are gone, or a code was accepted, the
+    /// token is dead and the login has to start over. Hands back the password
+    /// rehash the first factor left on the challenge, if any.
+    pub async fn verify_challenge(
+        app_state: &AppState,
+        challenge_id: Uuid,
+        user: &mfa::DBMfaQuery,
+        code: &str,
+    ) -> Result<Option<user::PasswordRehash>, AppError> {
+        // the attempt is taken before the code is checked, so parallel guesses can't overshoot
+        let attempt = sqlx::query_scalar::<_, i32>(
+            "UPDATE mfa_challenges SET attempts = attempts + 1
+             WHERE id = $1 AND user_id = $2 AND consumed_at IS NULL
+               AND expires_at > NOW() AND attempts < $3
+             RETURNING attempts",
+        )
+        .bind(challenge_id)
+        .bind(user.id)
+        .bind(app_state.mfa_config.max_challenge_attempts)
+        .fetch_optional(&app_state.pool)
+        .await
+        .map_err(AppError::database("recording MFA attempt"))?;
+
+        let Some(attempt) = attempt else {
+            tracing::warn!("MFA challenge used up, consumed or expired");
+            return Err(AppError::InvalidToken);
+        };
+
+        if let Err(e) = Self::verify_second_factor(app_state, user, code).await {
+            if attempt >= app_state.mfa_config.max_challenge_attempts {
+                tracing::warn!("MFA challenge out of attempts, the login has to start over");
+            }
+            return Err(e);
+        }
+
+        let consumed = sqlx::query_as::<_, (Option<String>, Option<String>)>(
+            "UPDATE mfa_challenges SET consumed_at = NOW() WHERE id = $1 AND consumed_at IS NULL
+             RETURNING new_password_hash, old_password_hash",
+        )
+        .bind(challenge_id)
+        .fetch_optional(&app_state.pool)
+        .await
+        .map_err(AppError::database("consuming MFA challenge"))?;
+
+        // a parallel attempt with another valid code got there first
+        let Some((new_hash, old_hash)) = consumed else {
+            return Err(AppError::InvalidToken);
+        };
+
+        Ok(new_hash
+            .zip(old_hash)
+            .map(|(new_hash, old_hash)| user::PasswordRehash { new_hash, old_hash }))
+    }
+
+    /// Checks a second factor for a user with TOTP enabled. `code` may be a
+    /// current TOTP code or an unused recovery code, which is burnt on success.
+    ///
+    /// Wrong codes count against the user whichever flow they come from, too
+    /// many in a row lock the second factor for `mfa.lockout_secs`.
+    pub async fn verify_second_factor(
+        app_state: &AppState,
+        user: &mfa::DBMfaQuery,
+        code: &str,
+    ) -> Result<(), AppError> {
+        let secret = match (&user.totp_secret, user.totp_enabled) {
+            (Some(secret), true) => secret,
+            _ => return Err(AppError::InvalidMfaCode),
+        };
+
+        let failed_attempts = Self::begin_attempt(app_state, user.id).await?;
+
+        match Self::check_code(app_state, user.id, secret, code.trim()).await {
+            Ok(()) => {
+                sqlx::query("UPDATE users SET mfa_failed_attempts = 0 WHERE id = $1")
+                    .bind(user.id)
+                    .execute(&app_state.pool)
+                    .await
+                    .map_err(AppError::database("resetting MFA attempts"))?;
+
+                Ok(())
+            }
+            Err(e) => {
+                if failed_attempts >= app_state.mfa_config.max_failed_attempts {
+                    Self::lock_out(app_state, user.id).await?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Counts an attempt against the user up front and returns the count,
+    /// or `MfaLocked` while a lockout is running.
+    async fn begin_attempt(app_state: &AppState, user_id: i64) -> Result<i32, AppError> {
+        let failed_attempts = sqlx::query_scalar::<_, i32>(
+            "UPDATE users SET mfa_failed_attempts = mfa_failed_attempts + 1
+             WHERE id = $1 AND (mfa_locked_until IS NULL OR mfa_locked_until <= NOW())
+             RETURNING mfa_failed_attempts",
+        )
+        .bind(user_id)
+        .fetch_optional(&app_state.pool)
+        .await
+        .map_err(AppError::database("recording MFA attempt"))?;
+
+        if let Some(failed_attempts) = failed_attempts {
+            return Ok(failed_attempts);
+        }
+
+        let locked_until = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+            "SELECT mfa_locked_until FROM users WHERE id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&app_state.pool)
+        .await
+        .map_err(AppError::database("loading MFA lockout"))?
+        .flatten()
+        .ok_or(AppError::NotFound)?;
+
+        tracing::warn!("Second factor refused, locked out until {}", locked_until);
+
+        Err(AppError::MfaLocked {
+            retry_after: (locked_until - Utc::now()).num_seconds().max(1) as u64,
+        })
+    }
+
+    async fn lock_out(app_state: &AppState, user_id: i64) -> Result<(), AppError> {
+        let locked_until = Utc::now() + app_state.mfa_config.lockout;
+
+        sqlx::query(
+            "UPDATE users SET mfa_locked_until = $1, mfa_failed_attempts = 0
+             WHERE id = $2 AND mfa_failed_attempts >= $3",
+        )
+        .bind(locked_until)
+        .bind(user_id)
+        .bind(app_state.mfa_config.max_failed_attempts)
+        .execute(&app_state.pool)
+        .await
+        .map_err(AppError::database("locking out second factor"))?;
+
+        tracing::warn!(
+            "Too many invalid second factor codes, locked out until {}",
+            locked_until
+        );
+
+        Ok(())
+    }
+
+    async fn check_code(
+        app_state: &AppState,
+        user_id: i64,
+        secret: &str,
+        code: &str,
+    ) -> Result<(), AppError> {
+        if TotpUtils::is_totp_code(code) {
+            let step = TotpUtils::verify_code(secret, code, Utc::now().timestamp() as u64)
+                .ok_or(AppError::InvalidMfaCode)?;
+
+            // only accept steps newer than the last one used, so a code can't be replayed
+            let result = sqlx::query(
+                "UPDATE users SET totp_last_step = $1
+                 WHERE id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)",
+            )
+            .bind(step as i64)
+            .bind(user_id)
+            .execute(&app_state.pool)
+            .await
+            .map_err(AppError::database("recording TOTP step"))?;
+
+            if result.rows_affected() == 0 {
+                tracing::warn!("Replayed TOTP code rejected");
+                return Err(AppError::InvalidMfaCode);
+            }
+
+            return Ok(());
+        }
+
+        Self::redeem_recovery_code(app_state, user_id, code).await
+    }
+
+    async fn redeem_recovery_code(
+        app_state: &AppState,
+        user_id: i64,
+        code: &str,
+    ) -> Result<(), AppError> {
+        let code = TotpUtils::normalize_recovery_code(code);
+
+        let unused_codes = sqlx::query_as::<_, mfa::DBRecoveryCode>(
+            "SELECT id, code_hash FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+        )
+        .bind(user_id)
+        .fetch_all(&app_state.pool)
+        .await
+        .map_err(AppError::database("loading recovery codes"))?;
+
+        let mut matched = None;
+        for stored in &unused_codes {
+            if app_state
+                .password_utils
+                .verfify_passowrd(&code, &stored.code_hash)
+                .await
+            {
+                matched = Some(stored);
+                break;
+            }
+        }
+        let matched = matched.ok_or(AppError::InvalidMfaCode)?;
+
+        let result = sqlx::query(
+            "UPDATE recovery_codes SET used_at = NOW() WHERE id = $1 AND used_at IS NULL",
+        )
+        .bind(matched.id)
+        .execute(&app_state.pool)
+        .await
+        .map_err(AppError::database("redeeming recovery code"))?;
+
+        if result.rows_affected() == 0 {
+            return Err(AppError::InvalidMfaCode);
+        }
+
+        tracing::info!("Recovery code redeemed");
+
+        Ok(())
+    }
+
+    pub async fn fetch_mfa_user(
+        app_state: &AppState,
+        user_id: i64,
+    ) -> Result<mfa::DBMfaQuery, AppError> {
+        sqlx::query_as::<_, mfa::DBMfaQuery>(
+            "SELECT id, username, totp_secret, totp_enabled FROM users WHERE id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&app_state.pool)
+        .await
+        .map_err(AppError::database("loading MFA settings"))?
+        .ok_or(AppError::NotFound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::{session::ClientInfo, user},
+        services::user::UserService,
+        test_support::{self, PASSWORD},
+    };
+    use sqlx::PgPool;
+
+    // shaped like a recovery code so it can never collide with a valid TOTP code
+    const WRONG_CODE: &str = "aaaaa-aaaaa";
+
+    async fn user_with_totp(app_state: &AppState, username: &str) -> String {
+        let user_id = test_support::create_user(app_state, username).await;
+        let secret = TotpUtils::generate_secret();
+
+        sqlx::query("UPDATE users SET totp_secret = $1, totp_enabled = TRUE WHERE id = $2")
+            .bind(&secret)
+            .bind(user_id)
+            .execute(&app_state.pool)
+            .await
+            .unwrap();
+
+        secret
+    }
+
+    async fn start_login(app_state: &AppState, username: &str) -> String {
+        let request = user::SignupAndLoginPayload {
+            username: username.to_string(),
+            password: PASSWORD.to_string(),
+        };
+
+        match UserService::login(app_state, request, ClientInfo::default()).await {
+            Ok(user::LoginResult::MfaRequired(challenge)) => challenge.mfa_token,
+            other => panic!("expected an MFA challenge, got {other:?}"),
+        }
+    }
+
+    async fn answer(app_state: &AppState, mfa_token: &str, code: &str) -> Result<(), AppError> {
+        let request = mfa::MfaLoginPayload {
+            mfa_token: mfa_token.to_string(),
+            code: code.to_string(),
+        };
+
+        UserService::complete_mfa_login(app_state, request, ClientInfo::default())
+            .await
+            .map(|_| ())
+    }
+
+    fn current_code(secret: &str) -> String {
+        TotpUtils::generate_code(secret, Utc::now().timestamp() as u64)
+    }
+
+    #[sqlx::test]
+    async fn challenge_is_dead_after_max_attempts(pool: PgPool) {
+        let app_state = test_support::app_state(pool);
+        let secret = user_with_totp(&app_state, "alice").await;
+        let mfa_token = start_login(&app_state, "alice").await;
+
+        for _ in 0..app_state.mfa_config.max_challenge_attempts {
+            let result = answer(&app_state, &mfa_token, WRONG_CODE).await;
+            assert!(
+                matches!(result, Err(AppError::InvalidMfaCode)),
+                "{result:?}"
+            );
+        }
+
+        // even the right code is refused now
+        let result = answer(&app_state, &mfa_token, &current_code(&secret)).await;
+        assert!(matches!(result, Err(AppError::InvalidToken)), "{result:?}");
+
+        // a new login gets a fresh challenge
+        let mfa_token = start_login(&app_state, "alice").await;
+        answer(&app_state, &mfa_token, &current_code(&secret))
+            .await
+            .expect("fresh challenge accepts a valid code");
+    }
+
+    #[sqlx::test]
+    async fn challenge_works_once(pool: PgPool) {
+        let app_state = test_support::app_state(pool);
+        let secret = user_with_totp(&app_state, "alice").await;
+        let mfa_token = start_login(&app_state, "alice").await;
+
+        answer(&app_state, &mfa_token, &current_code(&secret))
+            .await
+            .expect("valid code accepted");
+
+        let result = answer(&app_state, &mfa_token, WRONG_CODE).await;
+        assert!(matches!(result, Err(AppError::InvalidToken)), "{result:?}");
+    }
+
+    #[sqlx::test]
+    async fn user_is_locked_out_across_challenges(pool: PgPool) {
+        let config = test_support::config(&[
+            ("MFA_MAX_CHALLENGE_ATTEMPTS", "2"),
+            ("MFA_MAX_FAILED_ATTEMPTS", "3"),
+        ]);
+        let app_state = test_support::app_state_with(pool, config);
+        let secret = user_with_totp(&app_state, "alice").await;
+
+        for _ in 0..3 {
+            let mfa_token = start_login(&app_state, "alice").await;
+            let result = answer(&app_state, &mfa_token, WRONG_CODE).await;
+            assert!(
+                matches!(result, Err(AppError::InvalidMfaCode)),
+                "{result:?}"
+            );
+        }
+
+        let mfa_token = start_login(&app_state, "alice").await;
+        let result = answer(&app_state, &mfa_token, &current_code(&secret)).await;
+        assert!(
+            matches!(result, Err(AppError::MfaLocked { retry_after }) if retry_after > 0),
+            "{result:?}"
+        );
+    }
+
+    #[sqlx::test]
+    async fn second_factor_is_rate_limited_per_user(pool: PgPool) {
+        let config = test_support::config(&[("RATE_LIMIT_AUTH_BURST", "2")]);
+        let app_state = test_support::app_state_with(pool, config);
+        let secret = user_with_totp(&app_state, "alice").await;
+        let other_secret = user_with_totp(&app_state, "bob").await;
+
+        // a fresh challenge per guess doesn't get around it
+        for _ in 0..2 {
+            let mfa_token = start_login(&app_state, "alice").await;
+            assert!(answer(&app_state, &mfa_token, WRONG_CODE).await.is_err());
+        }
+        let mfa_token = start_login(&app_state, "alice").await;
+        let result = answer(&app_state, &mfa_token, &current_code(&secret)).await;
+        assert!(
+            matches!(result, Err(AppError::RateLimited { .. })),
+            "{result:?}"
+        );
+
+        let mfa_token = start_login(&app_state, "bob").await;
+        answer(&app_state, &mfa_token, &current_code(&other_secret))
+            .await
+            .expect("other users keep their own budget");
+    }
+
+    #[sqlx::test]
+    async fn valid_code_resets_failed_attempts(pool: PgPool) {
+        let config = test_support::config(&[("MFA_MAX_FAILED_ATTEMPTS", "2")]);
+        let app_state = test_support::app_state_with(pool, config);
+        let secret = user_with_totp(&app_state, "alice").await;
+
+        let mfa_token = start_login(&app_state, "alice").await;
+        assert!(answer(&app_state, &mfa_token, WRONG_CODE).await.is_err());
+        answer(&app_state, &mfa_token, &current_code(&secret))
+            .await
+            .expect("valid code accepted");
+
+        let mfa_token = start_login(&app_state, "alice").await;
+        let result = answer(&app_state, &mfa_token, WRONG_CODE).await;
+        assert!(
+            matches!(result, Err(AppError::InvalidMfaCode)),
+            "{result:?}"
+        );
+    }
+
+    #[sqlx::test]
+    async fn outdated_hash_is_upgraded_only_after_the_second_factor(pool: PgPool) {
+        let app_state = test_support::app_state(pool.clone());
+        let secret = user_with_totp(&app_state, "alice").await;
+        let password_hash = || async {
+            sqlx::query_scalar::<_, String>(
+                "SELECT password_hash FROM users WHERE username = 'alice'",
+            )
+            .fetch_one(&pool)
+            .await
+            .unwrap()
+        };
+        let old_hash = password_hash().await;
+
+        // stronger parameters than the user signed up with
+        let app_state = test_support::app_state_with(
+            pool.clone(),
+            test_support::config(&[("ARGON2_MEMORY_KIB", "2048")]),
+        );
+
+        let mfa_token = start_login(&app_state, "alice").await;
+        assert!(answer(&app_state, &mfa_token, WRONG_CODE).await.is_err());
+        assert_eq!(password_hash().await, old_hash);
+
+        answer(&app_state, &mfa_token, &current_code(&secret))
+            .await
+            .expect("valid code accepted");
+        let new_hash = password_hash().await;
+        assert_ne!(new_hash, old_hash);
+        assert!(!app_state.password_utils.needs_rehash(&new_hash));
+    }
+}
@@ -1,2 +1,11 @@
+pub mod account;
+pub mod admin;
+pub mod audit;
+pub mod health;
+pub mod metrics;
+pub mod mfa;
+pub mod oidc;
+pub mod session;
 pub mod task;
+pub mod token;
 pub mod user;
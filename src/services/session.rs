@@ -0,0 +1,150 @@
+use crate::{
+    AppState,
+    common::errors::AppError,
+    models::{
+        session::{self, ClientInfo},
+        user::Role,
+    },
+};
+use chrono::{Duration, Utc};
+use sqlx::types::ipnetwork::IpNetwork;
+use uuid::Uuid;
+
+// user agents are client controlled, keep what we store bounded
+const MAX_USER_AGENT_LEN: usize = 512;
+// avoid a write on every request, last_seen_at only needs to be roughly right
+const LAST_SEEN_RESOLUTION_SECONDS: i64 = 60;
+
+pub struct SessionService;
+
+impl SessionService {
+    /// Records a new login session, its id goes into the JWT as `sid`
+    /// together with the returned role. Disabled accounts get no session.
+    pub async fn create_session(
+        app_state: &AppState,
+        user_id: i64,
+        client: &ClientInfo,
+    ) -> Result<(Uuid, Role), AppError> {
+        let expires_at = Utc::now() + Duration::seconds(app_state.jwt_config.expiration);
+        let user_agent = client
+            .user_agent
+            .as_deref()
+            .map(|ua| ua.chars().take(MAX_USER_AGENT_LEN).collect::<String>());
+
+        let (session_id, role) = sqlx::query_as::<_, (Uuid, Role)>(
+            "WITH created AS (
+                INSERT INTO sessions (user_id, ip_address, user_agent, expires_at)
+                SELECT id, $2, $3, $4 FROM users WHERE id = $1 AND disabled_at IS NULL
+                RETURNING id, user_id
+             )
+             SELECT created.id, users.role FROM created JOIN users ON users.id = created.user_id",
+        )
+        .bind(user_id)
+        .bind(client.ip_address.map(IpNetwork::from))
+        .bind(user_agent)
+        .bind(expires_at)
+        .fetch_optional(&app_state.pool)
+        .await
+        .map_err(AppError::database("creating session"))?
+        .ok_or_else(|| {
+            tracing::warn!("Sign in refused for disabled user {}", user_id);
+            AppError::AccountDisabled
+        })?;
+
+        Ok((session_id, role))
+    }
+
+    pub async fn list_sessions(
+        app_state: &AppState,
+        user_id: i64,
+        current_session_id: Option<Uuid>,
+    ) -> Result<Vec<session::Session>, AppError> {
+        tracing::info!("Listing sessions");
+
+        let sessions = sqlx::query_as::<_, session::DBSession>(
+            "SELECT id, ip_address, user_agent, created_at, last_seen_at, expires_at
+             FROM sessions
+             WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+             ORDER BY last_seen_at DESC",
+        )
+        .bind(user_id)
+        .fetch_all(&app_state.pool)
+        .await
+        .map_err(AppError::database("listing sessions"))?;
+
+        Ok(sessions
+            .into_iter()
+            .map(|s| session::Session::from_db(s, current_session_id))
+            .collect())
+    }
+
+    pub async fn revoke_session(
+        app_state: &AppState,
+        user_id: i64,
+        session_id: Uuid,
+    ) -> Result<String, AppError> {
+        tracing::info!("Revoking session {}", session_id);
+
+        let result = sqlx::query(
+            "UPDATE sessions SET revoked_at = NOW()
+             WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(session_id)
+        .bind(user_id)
+        .execute(&app_state.pool)
+        .await
+        .map_err(AppError::database("revoking session"))?;
+
+        if result.rows_affected() == 0 {
+            tracing::warn!(%session_id, "No active session found to revoke");
+            return Err(AppError::NotFound);
+        }
+
+        Ok("Session revoked".to_string())
+    }
+
+    /// Rejects JWTs whose session was revoked or has expired, whose
+    /// `username` or `role` is stale, or whose account was disabled, and keeps
+    /// `last_seen_at` up to date for the ones that are still good.
+    pub async fn ensure_active(
+        app_state: &AppState,
+        user_id: i64,
+        username: &str,
+        role: Role,
+        session_id: Uuid,
+    ) -> Result<(), AppError> {
+        let state = sqlx::query_as::<_, session::DBSessionState>(
+            "SELECT sessions.revoked_at IS NULL
+                    AND sessions.expires_at > NOW()
+                    AND users.username = $3
+                    AND users.role = $4
+                    AND users.disabled_at IS NULL AS active,
+                    sessions.last_seen_at
+             FROM sessions JOIN users ON users.id = sessions.user_id
+             WHERE sessions.id = $1 AND sessions.user_id = $2",
+        )
+        .bind(session_id)
+        .bind(user_id)
+        .bind(username)
+        .bind(role)
+        .fetch_optional(&app_state.pool)
+        .await
+        .map_err(AppError::database("checking session"))?;
+
+        match state {
+            Some(state) if state.active => {
+                if state.last_seen_at < Utc::now() - Duration::seconds(LAST_SEEN_RESOLUTION_SECONDS)
+                {
+                    sqlx::query("UPDATE sessions SET last_seen_at = NOW() WHERE id = $1")
+                        .bind(session_id)
+                        .execute(&app_state.pool)
+                        .await
+                        .map_err(AppError::database("updating session"))?;
+                }
+
+                Ok(())
+            }
+            _ => Err(AppError::SessionRevoked),
+        }
+    }
+}
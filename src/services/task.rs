@@ -1,7 +1,6 @@
 use crate::{AppState, common::errors::AppError, models::task};
-use axum::Json;
 
-use chrono::{DateTime, Utc};
+use chrono::Utc;
 use uuid::Uuid;
 
 pub struct TaskServices;
@@ -16,12 +15,10 @@ impl TaskServices {
         tracing::info!("Editing fileds now");
 
         let old_task = sqlx::query_as!(task::Task, r#"SELECT * FROM tasks WHERE id = $1"#, task_id)
-            .fetch_one(&app_state.pool)
+            .fetch_optional(&app_state.pool)
             .await
-            .map_err(|e| {
-                tracing::error!("Task not found");
-                AppError::TaskNotFound
-            })?;
+            .map_err(AppError::database("loading task"))?
+            .ok_or(AppError::TaskNotFound)?;
 
         let updated_task = task::Task {
             id: old_task.id,
@@ -42,10 +39,7 @@ impl TaskServices {
         task_id )
             .fetch_one(&app_state.pool)
             .await
-            .map_err(|e| {
-  tracing::error!(error = ?e, "Failed to update task");
-        AppError::ErrorFetchingTasks
-            })?;
+            .map_err(AppError::database("updating task"))?;
 
         Ok(saved_task)
     }
@@ -62,10 +56,7 @@ impl TaskServices {
         .bind(user_id)
         .fetch_all(&app_state.pool)
         .await
-        .map_err(|e| {
-            tracing::error!("Error fetching user's tasks: {:?}", e);
-            AppError::ErrorFetchingTasks
-        })?;
+        .map_err(AppError::database("fetching user's tasks"))?;
 
         Ok(user_tasks)
     }
@@ -73,9 +64,9 @@ impl TaskServices {
     pub async fn create_task(
         app_state: &AppState,
         user_id: i64,
-        Json(task): Json<task::CreateTaskPayload>,
+        task: task::CreateTaskPayload,
     ) -> Result<task::TasksResponse, AppError> {
-        tracing::info!("Adding {} to db", task.title);
+        tracing::info!("Adding task to db");
 
         let status = task.status.unwrap_or(task::TaskStatus::Pending);
 
@@ -95,10 +86,8 @@ impl TaskServices {
         Utc::now()
         )
             .fetch_one(&app_state.pool)
-            .await.map_err(|e| {
-                tracing::info!("Error creating task");
-                AppError::TaskCreationFailed
-            })?;
+            .await
+            .map_err(AppError::database("creating task"))?;
 
         Ok(new_task)
     }
@@ -109,10 +98,7 @@ impl TaskServices {
         let result = sqlx::query!("DELETE FROM tasks WHERE id = $1", task_id)
             .execute(&app_state.pool)
             .await
-            .map_err(|e| {
-                tracing::error!(error =?e, "Error deleting task");
-                AppError::ErrorDeletingTask
-            })?;
+            .map_err(AppError::database("deleting task"))?;
 
         if result.rows_affected() == 0 {
             tracing::warn!(%task_id, "NO task found to delete");
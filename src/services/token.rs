@@ -0,0 +1,228 @@
+use crate::{
+    AppState,
+    common::{errors::AppError, utils::TokenUtils},
+    models::token,
+};
+use chrono::{Duration, Utc};
+
+// ten years, long enough for any automation and far from chrono's overflow
+pub const MAX_EXPIRES_IN_DAYS: i64 = 3650;
+// avoid a write on every request, last_used_at only needs to be roughly right
+const LAST_USED_RESOLUTION_SECONDS: i64 = 60;
+
+pub struct AccessTokenService;
+
+impl AccessTokenService {
+    pub async fn create_token(
+        app_state: &AppState,
+        user_id: i64,
+        request: token::CreateAccessTokenPayload,
+    ) -> Result<token::CreatedAccessTokenResponse, AppError> {
+        tracing::info!("Creating access token");
+
+        let name = request.name.trim();
+
+        if name.is_empty()
+            || request.scopes.is_empty()
+            || request
+                .expires_in_days
+                .is_some_and(|days| days <= 0 || days > MAX_EXPIRES_IN_DAYS)
+        {
+            return Err(AppError::InvalidAccessTokenRequest);
+        }
+
+        let mut scopes: Vec<String> = request.scopes.iter().map(|s| s.to_string()).collect();
+        scopes.sort();
+        scopes.dedup();
+
+        let expires_at = request
+            .expires_in_days
+            .map(|days| Utc::now() + Duration::days(days));
+
+        let raw_token = TokenUtils::generate_access_token();
+
+        let details = sqlx::query_as::<_, token::AccessToken>(
+            "INSERT INTO personal_access_tokens (user_id, name, token_prefix, token_hash, scopes, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             RETURNING id, name, token_prefix, scopes, created_at, last_used_at, expires_at",
+        )
+        .bind(user_id)
+        .bind(name)
+        .bind(TokenUtils::display_prefix(&raw_token))
+        .bind(TokenUtils::hash_token(&raw_token))
+        .bind(&scopes)
+        .bind(expires_at)
+        .fetch_one(&app_state.pool)
+        .await
+        .map_err(AppError::database("creating access token"))?;
+
+        Ok(token::CreatedAccessTokenResponse {
+            token: raw_token,
+            details,
+        })
+    }
+
+    pub async fn list_tokens(
+        app_state: &AppState,
+        user_id: i64,
+    ) -> Result<Vec<token::AccessToken>, AppError> {
+        tracing::info!("Listing access tokens");
+
+        sqlx::query_as::<_, token::AccessToken>(
+            "SELECT id, name, token_prefix, scopes, created_at, last_used_at, expires_at
+             FROM personal_access_tokens
+             WHERE user_id = $1 AND revoked_at IS NULL
+             ORDER BY created_at DESC",
+        )
+        .bind(user_id)
+        .fetch_all(&app_state.pool)
+        .await
+        .map_err(AppError::database("listing access tokens"))
+    }
+
+    pub async fn revoke_token(
+        app_state: &AppState,
+        user_id: i64,
+        token_id: i64,
+    ) -> Result<String, AppError> {
+        tracing::info!("Revoking access token {}", token_id);
+
+        let result = sqlx::query(
+            "UPDATE personal_access_tokens SET revoked_at = NOW()
+             WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL",
+        )
+        .bind(token_id)
+        .bind(user_id)
+        .execute(&app_state.pool)
+        .await
+        .map_err(AppError::database("revoking access token"))?;
+
+        if result.rows_affected() == 0 {
+            tracing::warn!(%token_id, "No active access token found to revoke");
+            return Err(AppError::NotFound);
+        }
+
+        Ok("Access token revoked".to_string())
+    }
+
+    /// Resolves a raw access token to its owner and scopes, bumping
+    /// `last_used_at` when it is more than a minute old.
+    ///
+    /// Revoked, expired and unknown tokens all come back as `InvalidToken`.
+    pub async fn authenticate(
+        app_state: &AppState,
+        raw_token: &str,
+    ) -> Result<(token::DBAccessTokenOwner, Vec<token::TokenScope>), AppError> {
+        let owner = sqlx::query_as::<_, token::DBAccessTokenOwner>(
+            "SELECT tokens.id, tokens.user_id, users.username, users.role, tokens.scopes,
+                    tokens.last_used_at
+             FROM personal_access_tokens AS tokens JOIN users ON users.id = tokens.user_id
+             WHERE tokens.token_hash = $1
+               AND tokens.revoked_at IS NULL
+               AND (tokens.expires_at IS NULL OR tokens.expires_at > NOW())
+               AND users.disabled_at IS NULL",
+        )
+        .bind(TokenUtils::hash_token(raw_token))
+        .fetch_optional(&app_state.pool)
+        .await
+        .map_err(AppError::database("checking access token"))?
+        .ok_or(AppError::InvalidToken)?;
+
+        let stale = owner.last_used_at.is_none_or(|last_used_at| {
+            last_used_at < Utc::now() - Duration::seconds(LAST_USED_RESOLUTION_SECONDS)
+        });
+        if stale {
+            sqlx::query("UPDATE personal_access_tokens SET last_used_at = NOW() WHERE id = $1")
+                .bind(owner.id)
+                .execute(&app_state.pool)
+                .await
+                .map_err(AppError::database("updating access token"))?;
+        }
+
+        // unknown scopes (e.g. from a newer release) are dropped rather than trusted
+        let scopes = owner
+            .scopes
+            .iter()
+            .filter_map(|s| s.parse::<token::TokenScope>().ok())
+            .collect();
+
+        Ok((owner, scopes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use sqlx::PgPool;
+
+    fn payload(expires_in_days: Option<i64>) -> token::CreateAccessTokenPayload {
+        token::CreateAccessTokenPayload {
+            name: "ci".to_string(),
+            scopes: vec![token::TokenScope::TasksRead],
+            expires_in_days,
+        }
+    }
+
+    async fn last_used_at(pool: &PgPool) -> Option<chrono::DateTime<Utc>> {
+        sqlx::query_scalar("SELECT last_used_at FROM personal_access_tokens")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test]
+    async fn expiry_out_of_range_is_refused(pool: PgPool) {
+        let app_state = test_support::app_state(pool);
+        let user_id = test_support::create_user(&app_state, "alice").await;
+
+        for days in [0, MAX_EXPIRES_IN_DAYS + 1, 1_000_000_000, i64::MAX] {
+            let result =
+                AccessTokenService::create_token(&app_state, user_id, payload(Some(days))).await;
+            assert!(
+                matches!(result, Err(AppError::InvalidAccessTokenRequest)),
+                "{days}: {result:?}"
+            );
+        }
+
+        let created = AccessTokenService::create_token(
+            &app_state,
+            user_id,
+            payload(Some(MAX_EXPIRES_IN_DAYS)),
+        )
+        .await
+        .expect("the maximum is allowed");
+        assert!(created.details.expires_at.is_some());
+    }
+
+    #[sqlx::test]
+    async fn last_used_at_is_written_at_most_once_a_minute(pool: PgPool) {
+        let app_state = test_support::app_state(pool.clone());
+        let user_id = test_support::create_user(&app_state, "alice").await;
+        let created = AccessTokenService::create_token(&app_state, user_id, payload(None))
+            .await
+            .unwrap();
+        assert_eq!(last_used_at(&pool).await, None);
+
+        AccessTokenService::authenticate(&app_state, &created.token)
+            .await
+            .unwrap();
+        let first_use = last_used_at(&pool).await.expect("set on first use");
+
+        AccessTokenService::authenticate(&app_state, &created.token)
+            .await
+            .unwrap();
+        assert_eq!(last_used_at(&pool).await, Some(first_use));
+
+        sqlx::query(
+            "UPDATE personal_access_tokens SET last_used_at = NOW() - INTERVAL '2 minutes'",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        AccessTokenService::authenticate(&app_state, &created.token)
+            .await
+            .unwrap();
+        assert!(last_used_at(&pool).await > Some(first_use));
+    }
+}
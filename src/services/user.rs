@@ -1,10 +1,30 @@
 use crate::{
     AppState,
-    common::{errors::AppError, jwt, utils::PasswordUtils},
-    models::user,
+    common::{
+        errors::AppError,
+        jwt,
+        rate_limit::{Client, RouteGroup},
+        redact,
+    },
+    models::{
+        audit::{AuditEventType, NewAuditEvent},
+        mfa,
+        session::ClientInfo,
+        user,
+    },
+    services::{
+        account::AccountService, audit::AuditService, mfa::MfaService, session::SessionService,
+    },
 };
 use chrono::Utc;
-use sqlx::PgPool;
+use uuid::Uuid;
+
+const MAX_USERNAME_LEN: usize = 64;
+const MAX_DISPLAY_NAME_LEN: usize = 100;
+const MAX_LOCALE_LEN: usize = 35;
+
+const PROFILE_COLUMNS: &str = "id, username, role, display_name, time_zone, locale, totp_enabled,
+     created_at AT TIME ZONE 'UTC' AS created_at, deletion_scheduled_at";
 
 pub struct UserService;
 
@@ -12,8 +32,29 @@ impl UserService {
     pub async fn create_user(
         app_state: &AppState,
         request: user::SignupAndLoginPayload,
+        client: ClientInfo,
+    ) -> Result<i64, AppError> {
+        let username = request.username.clone();
+        let result = Self::insert_user(app_state, request).await;
+
+        let event = match &result {
+            Ok(user_id) => {
+                NewAuditEvent::success(AuditEventType::SignUp, &client).user_id(*user_id)
+            }
+            Err(e) => NewAuditEvent::failure(AuditEventType::SignUp, &client, e),
+        };
+        AuditService::record(app_state, event.username(&username)).await;
+
+        result
+    }
+
+    async fn insert_user(
+        app_state: &AppState,
+        request: user::SignupAndLoginPayload,
     ) -> Result<i64, AppError> {
-        tracing::info!("Creating user: {}", request.username);
+        tracing::info!("Creating user: {}", redact::pii(&request.username));
+
+        validate_username(&request.username)?;
 
         // check for existing username
         let user_exists: bool =
@@ -21,26 +62,23 @@ impl UserService {
                 .bind(&request.username)
                 .fetch_one(&app_state.pool)
                 .await
-                .map_err(|e| {
-                    tracing::error!(
-                        "Database error checking username existence '{}': {:?}",
-                        request.username,
-                        e
-                    );
-                    AppError::DatabaseQueryFailed
-                })?;
+                .map_err(AppError::database("checking username existence"))?;
 
         if user_exists {
             return Err(AppError::UserAlreadyExists);
         }
 
-        let mut tx = app_state.pool.begin().await.map_err(|_| {
-            tracing::error!("Database error starting to pool for user creation");
-            AppError::DatabaseQueryFailed
-        })?;
+        // hash before taking a connection, hashing may queue behind other logins
+        let hashed_password = app_state
+            .password_utils
+            .hash_password(&request.password)
+            .await?;
 
-        // TODO: hash password
-        let hashed_password = PasswordUtils::hash_password(&request.password)?;
+        let mut tx = app_state
+            .pool
+            .begin()
+            .await
+            .map_err(AppError::database("starting user creation"))?;
 
         // insert user and hashed password into DB
         // Create user
@@ -54,22 +92,15 @@ impl UserService {
         .bind(Utc::now().naive_utc())
         .fetch_one(&mut *tx)
         .await
-        .map_err(|e| {
-            tracing::error!(
-                "Database error creating user '{}': {:?}",
-                request.username,
-                e
-            );
-            AppError::DatabaseQueryFailed
+        .map_err(|e| match e {
+            // lost a race against another sign up
+            sqlx::Error::Database(db) if db.is_unique_violation() => AppError::UserAlreadyExists,
+            e => AppError::database("creating user")(e),
         })?;
 
-        tx.commit().await.map_err(|e| {
-            tracing::error!(
-                "Database error committing user creation transaction: {:?}",
-                e
-            );
-            AppError::DatabaseQueryFailed
-        })?;
+        tx.commit()
+            .await
+            .map_err(AppError::database("committing user creation transaction"))?;
 
         Ok(new_user_id)
     }
@@ -77,48 +108,543 @@ impl UserService {
     pub async fn login(
         app_state: &AppState,
         request: user::SignupAndLoginPayload,
-    ) -> Result<user::LoginResponse, AppError> {
+        client: ClientInfo,
+    ) -> Result<user::LoginResult, AppError> {
         tracing::info!("Attempting login");
 
         let user = sqlx::query_as::<_, user::DBUserQuery>(
-            "SELECT id, username, password_hash FROM users WHERE username=$1",
+            "SELECT id, username, password_hash, totp_enabled FROM users WHERE username=$1",
         )
         .bind(&request.username)
         .fetch_optional(&app_state.pool)
         .await
-        .map_err(|e| {
-            tracing::error!("Error signing user in'{}': {:?}", request.username, e);
-            AppError::DatabaseQueryFailed
-        })?;
+        .map_err(AppError::database("signing user in"))?;
 
         let user = match user {
             Some(u) => u,
-            None => return Err(AppError::InvalidUserCredentials),
+            None => {
+                AuditService::record(
+                    app_state,
+                    NewAuditEvent::failure(AuditEventType::Login, &client, "unknown username")
+                        .username(&request.username),
+                )
+                .await;
+
+                return Err(AppError::InvalidUserCredentials);
+            }
         };
 
-        if !PasswordUtils::verfify_passowrd(&request.password.to_string(), &user.password_hash) {
+        if !app_state
+            .password_utils
+            .verfify_passowrd(&request.password, &user.password_hash)
+            .await
+        {
             tracing::warn!("Invalid login attempt");
 
+            AuditService::record(
+                app_state,
+                NewAuditEvent::failure(AuditEventType::Login, &client, "wrong password")
+                    .user_id(user.id)
+                    .username(&user.username),
+            )
+            .await;
+
             return Err(AppError::InvalidUserCredentials);
         }
 
+        // only now do we have the plain password to upgrade an old hash with
+        let rehash = match app_state.password_utils.needs_rehash(&user.password_hash) {
+            true => Self::rehash_password(app_state, &request.password, &user.password_hash).await,
+            false => None,
+        };
+
+        Self::finish_login(
+            app_state,
+            user.id,
+            user.username,
+            user.totp_enabled,
+            rehash,
+            &client,
+            AuditEventType::Login,
+        )
+        .await
+    }
+
+    /// Ends every sign in once the first factor passed: users with TOTP
+    /// enabled get an MFA challenge, everyone else a session and JWT. A
+    /// `rehash` is only stored once the whole sign in succeeded.
+    pub async fn finish_login(
+        app_state: &AppState,
+        user_id: i64,
+        username: String,
+        totp_enabled: bool,
+        rehash: Option<user::PasswordRehash>,
+        client: &ClientInfo,
+        event_type: AuditEventType,
+    ) -> Result<user::LoginResult, AppError> {
+        if totp_enabled {
+            tracing::info!("First factor accepted, waiting for second factor");
+
+            let challenge = MfaService::create_challenge(app_state, user_id, rehash).await?;
+
+            AuditService::record(
+                app_state,
+                NewAuditEvent::success(AuditEventType::MfaChallenge, client)
+                    .user_id(user_id)
+                    .username(&username),
+            )
+            .await;
+
+            return Ok(user::LoginResult::MfaRequired(challenge));
+        }
+
         tracing::info!("Login successful");
 
-        // generate JWT
-        let token =
-            jwt::generate_token(&user.username, user.id, &app_state.jwt_config).map_err(|_| {
-                tracing::error!("Error generating jwt for user {}", user.username);
+        if let Some(rehash) = rehash {
+            Self::store_rehash(app_state, user_id, rehash).await;
+        }
+
+        let response =
+            Self::issue_login_response(app_state, user_id, username, client, event_type).await?;
+
+        Ok(user::LoginResult::Authenticated(response))
+    }
+
+    /// Second step of a TOTP login, exchanges the mfa pending token and a
+    /// valid code for a regular JWT.
+    pub async fn complete_mfa_login(
+        app_state: &AppState,
+        request: mfa::MfaLoginPayload,
+        client: ClientInfo,
+    ) -> Result<user::LoginResponse, AppError> {
+        tracing::info!("Attempting second factor login");
+
+        let claims = match jwt::verify_mfa_pending_token(&request.mfa_token, &app_state.jwt_keys) {
+            Ok(claims) => claims,
+            Err(e) => {
+                let reason = jwt::rejection_reason(&e);
+                tracing::warn!("MFA token verification failed: {} ({:?})", reason, e);
+
+                AuditService::record(
+                    app_state,
+                    NewAuditEvent::failure(AuditEventType::MfaLogin, &client, &reason),
+                )
+                .await;
+
+                return Err(reason);
+            }
+        };
 
-                AppError::JWTCreationFailed
+        // the route is limited per IP, guesses spread over many addresses are
+        // held back per user too
+        app_state
+            .rate_limiter
+            .enforce(RouteGroup::Auth, Client::User(claims.user_id))
+            .await?;
+
+        let challenge_id = Uuid::parse_str(&claims.jti).map_err(|_| AppError::InvalidToken)?;
+
+        let user = MfaService::fetch_mfa_user(app_state, claims.user_id)
+            .await
+            .map_err(|e| match e {
+                AppError::NotFound => AppError::InvalidToken,
+                e => e,
             })?;
 
-        tracing::info!("JWT successfully created for: {}", &user.username);
-        tracing::info!("{} was successfully logged in", &user.username);
+        let rehash =
+            match MfaService::verify_challenge(app_state, challenge_id, &user, &request.code).await
+            {
+                Ok(rehash) => rehash,
+                Err(e) => {
+                    tracing::warn!("Invalid second factor login attempt");
+
+                    AuditService::record(
+                        app_state,
+                        NewAuditEvent::failure(AuditEventType::MfaLogin, &client, &e)
+                            .user_id(user.id)
+                            .username(&user.username),
+                    )
+                    .await;
+
+                    return Err(e);
+                }
+            };
+
+        tracing::info!("Login successful");
+
+        if let Some(rehash) = rehash {
+            Self::store_rehash(app_state, user.id, rehash).await;
+        }
+
+        Self::issue_login_response(
+            app_state,
+            user.id,
+            user.username,
+            &client,
+            AuditEventType::MfaLogin,
+        )
+        .await
+    }
+
+    /// Hashes the password again for a hash made with outdated Argon2
+    /// parameters. Failures are only logged, the old hash still works.
+    async fn rehash_password(
+        app_state: &AppState,
+        password: &str,
+        old_hash: &str,
+    ) -> Option<user::PasswordRehash> {
+        match app_state.password_utils.hash_password(password).await {
+            Ok(new_hash) => Some(user::PasswordRehash {
+                new_hash,
+                old_hash: old_hash.to_string(),
+            }),
+            Err(e) => {
+                tracing::error!("Rehashing password failed: {:?}", e);
+                None
+            }
+        }
+    }
+
+    /// Replaces the old hash once the sign in succeeded. Failures are only
+    /// logged, the login itself already went through.
+    async fn store_rehash(app_state: &AppState, user_id: i64, rehash: user::PasswordRehash) {
+        // the old hash in the WHERE stops us clobbering a concurrent password change
+        let result =
+            sqlx::query("UPDATE users SET password_hash = $1 WHERE id = $2 AND password_hash = $3")
+                .bind(&rehash.new_hash)
+                .bind(user_id)
+                .bind(&rehash.old_hash)
+                .execute(&app_state.pool)
+                .await;
+
+        match result {
+            Ok(_) => tracing::info!("Upgraded password hash parameters for user {}", user_id),
+            Err(e) => tracing::error!("Database error storing rehashed password: {:?}", e),
+        }
+    }
+
+    /// Opens a session for `client`, issues the JWT bound to it and records
+    /// the successful `event_type` in the audit log.
+    pub async fn issue_login_response(
+        app_state: &AppState,
+        user_id: i64,
+        username: String,
+        client: &ClientInfo,
+        event_type: AuditEventType,
+    ) -> Result<user::LoginResponse, AppError> {
+        let (session_id, role) =
+            match SessionService::create_session(app_state, user_id, client).await {
+                Ok(created) => created,
+                Err(e) => {
+                    AuditService::record(
+                        app_state,
+                        NewAuditEvent::failure(event_type, client, &e)
+                            .user_id(user_id)
+                            .username(&username),
+                    )
+                    .await;
+
+                    return Err(e);
+                }
+            };
+
+        // generate JWT
+        let token = jwt::generate_token(
+            &username,
+            user_id,
+            role,
+            session_id,
+            &app_state.jwt_config,
+            &app_state.jwt_keys,
+        )
+        .map_err(|_| {
+            tracing::error!("Error generating jwt for user {}", redact::pii(&username));
+
+            AppError::JWTCreationFailed
+        })?;
+
+        tracing::info!("JWT successfully created for: {}", redact::pii(&username));
+        tracing::info!("{} was successfully logged in", redact::pii(&username));
+
+        AuditService::record(
+            app_state,
+            NewAuditEvent::success(event_type, client)
+                .user_id(user_id)
+                .username(&username),
+        )
+        .await;
 
         Ok(user::LoginResponse {
             token,
-            username: request.username,
-            user_id: user.id,
+            username,
+            user_id,
+            role,
+        })
+    }
+
+    pub async fn get_profile(
+        app_state: &AppState,
+        user_id: i64,
+    ) -> Result<user::UserProfile, AppError> {
+        sqlx::query_as::<_, user::UserProfile>(&format!(
+            "SELECT {} FROM users WHERE id = $1",
+            PROFILE_COLUMNS
+        ))
+        .bind(user_id)
+        .fetch_optional(&app_state.pool)
+        .await
+        .map_err(AppError::database("loading profile"))?
+        .ok_or(AppError::NotFound)
+    }
+
+    pub async fn update_profile(
+        app_state: &AppState,
+        user_id: i64,
+        request: user::UpdateProfilePayload,
+    ) -> Result<user::UserProfile, AppError> {
+        tracing::info!("Updating profile");
+
+        let display_name = request.display_name.as_deref().map(str::trim);
+        if display_name.is_some_and(|name| name.chars().count() > MAX_DISPLAY_NAME_LEN) {
+            tracing::warn!("Display name too long");
+            return Err(AppError::invalid_field(
+                "display_name",
+                "must be at most 100 characters",
+            ));
+        }
+
+        let locale = request.locale.as_deref().map(str::trim);
+        if locale.is_some_and(|locale| !is_locale_tag(locale)) {
+            tracing::warn!("Invalid locale {:?}", locale);
+            return Err(AppError::invalid_field(
+                "locale",
+                "is not a language tag such as en-GB",
+            ));
+        }
+
+        let time_zone = request.time_zone.as_deref().map(str::trim);
+        if let Some(time_zone) = time_zone {
+            // postgres ships the IANA database, no need for our own copy
+            let known: bool = sqlx::query_scalar(
+                "SELECT EXISTS(SELECT 1 FROM pg_timezone_names WHERE name = $1)",
+            )
+            .bind(time_zone)
+            .fetch_one(&app_state.pool)
+            .await
+            .map_err(AppError::database("checking time zone"))?;
+
+            if !known {
+                tracing::warn!("Unknown time zone {:?}", time_zone);
+                return Err(AppError::invalid_field(
+                    "time_zone",
+                    "is not an IANA time zone such as Europe/Berlin",
+                ));
+            }
+        }
+
+        sqlx::query_as::<_, user::UserProfile>(&format!(
+            "UPDATE users SET
+                display_name = CASE WHEN $2::TEXT IS NULL THEN display_name ELSE NULLIF($2, '') END,
+                time_zone = COALESCE($3, time_zone),
+                locale = COALESCE($4, locale)
+             WHERE id = $1
+             RETURNING {}",
+            PROFILE_COLUMNS
+        ))
+        .bind(user_id)
+        .bind(display_name)
+        .bind(time_zone)
+        .bind(locale)
+        .fetch_optional(&app_state.pool)
+        .await
+        .map_err(AppError::database("updating profile"))?
+        .ok_or(AppError::NotFound)
+    }
+
+    /// Renames the user once their password (and second factor) is confirmed.
+    /// Every existing session is signed out since their JWTs carry the old
+    /// `username`, the caller gets a fresh token instead.
+    pub async fn change_username(
+        app_state: &AppState,
+        user_id: i64,
+        session_id: Option<Uuid>,
+        request: user::ChangeUsernamePayload,
+        client: ClientInfo,
+    ) -> Result<user::LoginResponse, AppError> {
+        tracing::info!("Changing username");
+
+        let username = request.username.trim();
+        validate_username(username)?;
+
+        if let Err(e) = AccountService::reauthenticate(
+            app_state,
+            user_id,
+            session_id,
+            &request.reauthentication,
+        )
+        .await
+        {
+            AuditService::record(
+                app_state,
+                NewAuditEvent::failure(AuditEventType::UsernameChanged, &client, &e)
+                    .user_id(user_id),
+            )
+            .await;
+
+            return Err(e);
+        }
+
+        let mut tx = app_state
+            .pool
+            .begin()
+            .await
+            .map_err(AppError::database("starting username change"))?;
+
+        let user_exists: bool =
+            sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM users WHERE username = $1)")
+                .bind(username)
+                .fetch_one(&mut *tx)
+                .await
+                .map_err(AppError::database("checking username existence"))?;
+
+        if user_exists {
+            return Err(AppError::UserAlreadyExists);
+        }
+
+        sqlx::query("UPDATE users SET username = $1 WHERE id = $2")
+            .bind(username)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| match e {
+                // lost a race against another rename or sign up
+                sqlx::Error::Database(db) if db.is_unique_violation() => {
+                    AppError::UserAlreadyExists
+                }
+                e => AppError::database("changing username")(e),
+            })?;
+
+        sqlx::query(
+            "UPDATE sessions SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL",
+        )
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::database("revoking sessions"))?;
+
+        tx.commit()
+            .await
+            .map_err(AppError::database("committing username change"))?;
+
+        Self::issue_login_response(
+            app_state,
+            user_id,
+            username.to_string(),
+            &client,
+            AuditEventType::UsernameChanged,
+        )
+        .await
+    }
+}
+
+/// Loose BCP 47 check: a 2-3 letter language optionally followed by
+/// alphanumeric subtags, e.g. `en`, `en-GB`, `zh-Hant-TW`.
+fn is_locale_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+
+    let language_ok = subtags.next().is_some_and(|lang| {
+        (2..=3).contains(&lang.len()) && lang.chars().all(|c| c.is_ascii_alphabetic())
+    });
+
+    language_ok
+        && tag.len() <= MAX_LOCALE_LEN
+        && subtags.all(|sub| {
+            (1..=8).contains(&sub.len()) && sub.chars().all(|c| c.is_ascii_alphanumeric())
         })
+}
+
+/// Same rules for sign up and renames, 1 to 64 characters without whitespace.
+fn validate_username(username: &str) -> Result<(), AppError> {
+    let message = if username.is_empty() {
+        "is required"
+    } else if username.chars().count() > MAX_USERNAME_LEN {
+        "must be at most 64 characters"
+    } else if username.chars().any(char::is_whitespace) {
+        "must not contain whitespace"
+    } else {
+        return Ok(());
+    };
+
+    tracing::warn!("Invalid username");
+    Err(AppError::invalid_field("username", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::account::ReauthenticatePayload,
+        test_support::{self, PASSWORD},
+    };
+    use sqlx::PgPool;
+
+    fn rename(username: &str, password: &str) -> user::ChangeUsernamePayload {
+        user::ChangeUsernamePayload {
+            username: username.to_string(),
+            reauthentication: ReauthenticatePayload {
+                password: Some(password.to_string()),
+                code: None,
+            },
+        }
+    }
+
+    #[sqlx::test]
+    async fn username_change_needs_the_password(pool: PgPool) {
+        let app_state = test_support::app_state(pool);
+        let user_id = test_support::create_user(&app_state, "alice").await;
+
+        let result = UserService::change_username(
+            &app_state,
+            user_id,
+            None,
+            rename("mallory", "wrong"),
+            ClientInfo::default(),
+        )
+        .await;
+        assert!(
+            matches!(result, Err(AppError::InvalidUserCredentials)),
+            "{result:?}"
+        );
+        let profile = UserService::get_profile(&app_state, user_id).await.unwrap();
+        assert_eq!(profile.username, "alice");
+
+        let response = UserService::change_username(
+            &app_state,
+            user_id,
+            None,
+            rename("alice2", PASSWORD),
+            ClientInfo::default(),
+        )
+        .await
+        .expect("renamed");
+        assert_eq!(response.username, "alice2");
+    }
+
+    #[sqlx::test]
+    async fn invalid_username_is_a_field_error(pool: PgPool) {
+        let app_state = test_support::app_state(pool);
+        let request = user::SignupAndLoginPayload {
+            username: "two words".to_string(),
+            password: PASSWORD.to_string(),
+        };
+
+        let result = UserService::create_user(&app_state, request, ClientInfo::default()).await;
+        match result {
+            Err(AppError::Validation(fields)) => {
+                assert_eq!(fields.len(), 1);
+                assert_eq!(fields[0].field, "username");
+            }
+            other => panic!("expected a validation error, got {other:?}"),
+        }
     }
 }
@@ -0,0 +1,71 @@
+//! Shared setup for tests that need an `AppState`, usually around the
+//! database a `#[sqlx::test]` hands out.
+
+use crate::{
+    AppState,
+    common::{
+        jwt::JwtKeys, metrics::Metrics, oidc::OidcClient, rate_limit::RateLimiter,
+        shutdown::Shutdown, utils::PasswordUtils,
+    },
+    config::Config,
+    models::{session::ClientInfo, user},
+    services::user::UserService,
+};
+use sqlx::PgPool;
+use std::sync::Arc;
+
+// dev mode for the built-in JWT secret, and cheap hashing so tests stay fast
+const BASE_VARS: &[(&str, &str)] = &[
+    ("APP_ENV", "dev"),
+    ("DATABASE_URL", "postgres://localhost/tasks_test"),
+    ("ARGON2_MEMORY_KIB", "1024"),
+    ("ARGON2_TIME_COST", "1"),
+];
+
+/// The config tests run with, `vars` override the environment defaults.
+pub fn config(vars: &[(&str, &str)]) -> Config {
+    let vars: Vec<_> = BASE_VARS.iter().chain(vars).copied().collect();
+
+    Config::from_sources("", &vars).expect("valid test config")
+}
+
+pub fn app_state(pool: PgPool) -> AppState {
+    app_state_with(pool, config(&[]))
+}
+
+pub fn app_state_with(pool: PgPool, config: Config) -> AppState {
+    let rate_limiter = RateLimiter::new(config.rate_limit_config, &pool);
+
+    AppState {
+        pool,
+        jwt_keys: Arc::new(JwtKeys::from_config(&config.jwt_config).expect("test JWT keys")),
+        jwt_config: config.jwt_config,
+        password_utils: PasswordUtils::new(&config.password_config).expect("test Argon2 params"),
+        mfa_config: config.mfa_config,
+        oidc_client: config
+            .oidc_config
+            .map(|oidc_config| Arc::new(OidcClient::new(oidc_config).expect("OIDC client"))),
+        audit_config: config.audit_config,
+        account_deletion_config: config.account_deletion_config,
+        admin_config: config.admin_config,
+        health_config: config.health_config,
+        api_config: config.api_config,
+        rate_limiter: Arc::new(rate_limiter),
+        metrics: Arc::new(Metrics::new().expect("metrics registry")),
+        shutdown: Shutdown::new(),
+    }
+}
+
+pub const PASSWORD: &str = "Password123!";
+
+/// Signs up `username` with [`PASSWORD`].
+pub async fn create_user(app_state: &AppState, username: &str) -> i64 {
+    let request = user::SignupAndLoginPayload {
+        username: username.to_string(),
+        password: PASSWORD.to_string(),
+    };
+
+    UserService::create_user(app_state, request, ClientInfo::default())
+        .await
+        .expect("test user created")
+}